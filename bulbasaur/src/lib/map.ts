@@ -11,6 +11,14 @@ const colorMap = {
         value: "#0000FF",
         display: "Unallocated"
     },
+    "multicast-*-*": {
+        value: "#AA00FF",
+        display: "Multicast"
+    },
+    "bogon-*-*": {
+        value: "#662200",
+        display: "Bogon"
+    },
     "allocated-false-false": {
         value: "#FFFF00",
         display: "Allocated unrouted"
@@ -36,6 +44,12 @@ function mapToColorId(allocation_state: string, routed: boolean, online: boolean
         case "unallocated":
             return "unallocated-*-*";
 
+        case "multicast":
+            return "multicast-*-*";
+
+        case "bogon":
+            return "bogon-*-*";
+
         case "allocated":
         default:
             return `allocated-${routed.toString()}-${online.toString()}` as keyof typeof colorMap;
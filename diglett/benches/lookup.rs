@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use diglett::index::PrefixIndex;
+
+// Small xorshift generator so the benchmark is deterministic without extra dependencies
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+// Roughly the shape of a full routing table: mostly /24s, nested inside shorter covering prefixes
+fn table(size: usize) -> Vec<(u32, u32, u32)> {
+    let mut rng = XorShift(0x2545F491);
+    let mut ranges = Vec::with_capacity(size);
+
+    for i in 0..size {
+        let mask = match rng.next() % 10 {
+            0 => 8 + rng.next() % 8,
+            1..=3 => 16 + rng.next() % 8,
+            _ => 24,
+        };
+        let bitmask = u32::MAX << (32 - mask);
+        let start = rng.next() & bitmask;
+        ranges.push((start, start | !bitmask, i as u32));
+    }
+
+    ranges
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let index = PrefixIndex::build(table(1_000_000));
+    let mut rng = XorShift(0x9E3779B9);
+    let addresses = (0..4096).map(|_| rng.next()).collect::<Vec<_>>();
+
+    let mut i = 0;
+    c.bench_function("lookup (1M prefixes)", |b| {
+        b.iter(|| {
+            i = (i + 1) % addresses.len();
+            index.lookup(black_box(addresses[i]))
+        })
+    });
+}
+
+fn bench_build(c: &mut Criterion) {
+    let ranges = table(100_000);
+
+    c.bench_function("build (100k prefixes)", |b| {
+        b.iter(|| PrefixIndex::build(black_box(ranges.clone())))
+    });
+}
+
+criterion_group!(benches, bench_lookup, bench_build);
+criterion_main!(benches);
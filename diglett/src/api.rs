@@ -1,54 +1,63 @@
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     middleware,
-    response::IntoResponse,
-    routing::get,
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
+use futures::{stream, StreamExt};
 use mtilib::{
     auth::{GetJWTKeys, JWTKeys},
-    types::{AllocationState, ValueResponse},
+    types::{
+        AddressResponse, AllocationResponse, AsnNameResponse, AsnResponse, ChangesResponse,
+        GeoResponse, HolderResponse, LookupRequest, LookupResponse, PrefixesResponse, Rir,
+        RoutedResponse, RpkiResponse, ValueResponse,
+    },
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::RwLock;
 use tower_http::trace::TraceLayer;
 use tracing::info;
 use uuid::Uuid;
 
-use crate::{providers::Providers, settings::Settings};
+use crate::{
+    changes,
+    providers::{download, PrefixFilter, Providers},
+    report::{self, InconsistencyKind, Report},
+    settings::Settings,
+    utils::{self, CIDR},
+};
+
+async fn get_address(
+    Path(address): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AddressResponse>, StatusCode> {
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => Ok(Json(state.providers.read().await.address(address))),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
 
 async fn get_allocation(
     Path(address): Path<String>,
     State(state): State<AppState>,
-) -> Result<Json<ValueResponse<String>>, StatusCode> {
-    match Ipv4Addr::from_str(address.trim()) {
+) -> Result<Json<AllocationResponse>, StatusCode> {
+    match IpAddr::from_str(address.trim()) {
         Ok(address) => {
-            let address_bits: u32 = address.into();
+            let providers = state.providers.read().await;
 
-            for entry in state.providers.read().await.iana.reserved.values.iter() {
-                if entry.address_is_in(address_bits) {
-                    return Ok(Json(ValueResponse {
-                        value: AllocationState::Reserved.id().to_string(),
-                    }));
-                }
-            }
-
-            for entry in state.providers.read().await.stats.values.iter() {
-                if entry.cidr.address_is_in(address_bits) {
-                    return Ok(Json(ValueResponse {
-                        value: entry.allocation_state.id().to_string(),
-                    }));
-                }
-            }
-
-            Ok(Json(ValueResponse {
-                value: AllocationState::Unknown.id().to_string(),
+            Ok(Json(AllocationResponse {
+                value: providers.allocation_state(address).id().to_string(),
+                special_purpose: providers.special_purpose(address),
             }))
         }
         Err(_) => Err(StatusCode::BAD_REQUEST),
@@ -66,49 +75,17 @@ async fn get_rir(
     Query(query): Query<RirQuery>,
     State(state): State<AppState>,
 ) -> Result<Json<ValueResponse<Option<String>>>, StatusCode> {
-    match Ipv4Addr::from_str(address.trim()) {
+    match IpAddr::from_str(address.trim()) {
         Ok(address) => {
-            let address_bits: u32 = address.into();
-
-            if query.top {
-                // Use thyme allocations as top
-                for entry in state
-                    .providers
-                    .read()
-                    .await
-                    .thyme
-                    .rir_allocations
-                    .values
-                    .iter()
-                {
-                    if entry.cidr.address_is_in(address_bits) {
-                        return Ok(Json(ValueResponse {
-                            value: Some(entry.rir.id().to_string()),
-                        }));
-                    }
-                }
-            } else {
-                // First look up the IANA recovered addresses
-                for entry in state.providers.read().await.iana.recovered.values.iter() {
-                    if address_bits >= entry.start.to_bits() && address_bits <= entry.end.to_bits()
-                    {
-                        return Ok(Json(ValueResponse {
-                            value: Some(entry.rir.id().to_string()),
-                        }));
-                    }
-                }
-
-                // Then look up the ARIN stat files
-                for entry in state.providers.read().await.stats.values.iter() {
-                    if entry.cidr.address_is_in(address_bits) {
-                        return Ok(Json(ValueResponse {
-                            value: Some(entry.rir.id().to_string()),
-                        }));
-                    }
-                }
-            }
+            let providers = state.providers.read().await;
+            let rir = match query.top {
+                true => providers.top_rir(address),
+                false => providers.rir(address),
+            };
 
-            Ok(Json(ValueResponse { value: None }))
+            Ok(Json(ValueResponse {
+                value: rir.map(|rir| rir.id().to_string()),
+            }))
         }
         Err(_) => Err(StatusCode::BAD_REQUEST),
     }
@@ -118,28 +95,10 @@ async fn get_asn(
     Path(address): Path<String>,
     State(state): State<AppState>,
 ) -> Result<Json<ValueResponse<Option<u32>>>, StatusCode> {
-    match Ipv4Addr::from_str(address.trim()) {
-        Ok(address) => {
-            let address_bits: u32 = address.into();
-
-            for entry in state
-                .providers
-                .read()
-                .await
-                .thyme
-                .asn_prefixes
-                .values
-                .iter()
-            {
-                if entry.cidr.address_is_in(address_bits) {
-                    return Ok(Json(ValueResponse {
-                        value: Some(entry.asn),
-                    }));
-                }
-            }
-
-            Ok(Json(ValueResponse { value: None }))
-        }
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => Ok(Json(ValueResponse {
+            value: state.providers.read().await.asn(address),
+        })),
         Err(_) => Err(StatusCode::BAD_REQUEST),
     }
 }
@@ -148,22 +107,416 @@ async fn get_country(
     Path(address): Path<String>,
     State(state): State<AppState>,
 ) -> Result<Json<ValueResponse<Option<String>>>, StatusCode> {
-    match Ipv4Addr::from_str(address.trim()) {
-        Ok(address) => {
-            let address_bits: u32 = address.into();
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => Ok(Json(ValueResponse {
+            value: state.providers.read().await.country(address),
+        })),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_geo(
+    Path(address): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<GeoResponse>, StatusCode> {
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => match state.providers.read().await.geo(address) {
+            Some(geo) => Ok(Json(geo)),
+            None => Err(StatusCode::NOT_FOUND),
+        },
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_routed(
+    Path(address): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<RoutedResponse>, StatusCode> {
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => Ok(Json(state.providers.read().await.routed(address))),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_rpki(
+    Path(address): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<RpkiResponse>, StatusCode> {
+    match IpAddr::from_str(address.trim()) {
+        Ok(address) => match state.providers.read().await.rpki(address) {
+            Some(rpki) => Ok(Json(rpki)),
+            // No ROAs are loaded
+            None => Err(StatusCode::NOT_FOUND),
+        },
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Accepts both the plain number and the AS123 notation
+fn parse_asn(asn: &str) -> Option<u32> {
+    let asn = asn.trim();
+    let asn = match asn.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &asn[2..],
+        _ => asn,
+    };
+
+    asn.parse().ok()
+}
+
+async fn get_autsys(
+    Path(asn): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AsnResponse>, StatusCode> {
+    match parse_asn(&asn) {
+        Some(asn) => match state.providers.read().await.autsys(asn) {
+            Some(autsys) => Ok(Json(autsys)),
+            None => Err(StatusCode::NOT_FOUND),
+        },
+        None => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_autsys_name(
+    Path(asn): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AsnNameResponse>, StatusCode> {
+    match parse_asn(&asn) {
+        Some(asn) => match state.providers.read().await.autsys_name(asn) {
+            Some(name) => Ok(Json(name)),
+            None => Err(StatusCode::NOT_FOUND),
+        },
+        None => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+#[derive(Deserialize)]
+struct PrefixesQuery {
+    #[serde(default)]
+    aggregate: bool,
+}
+
+async fn prefixes(
+    state: AppState,
+    filter: PrefixFilter,
+    query: PrefixesQuery,
+) -> Result<Json<PrefixesResponse>, StatusCode> {
+    match state
+        .providers
+        .read()
+        .await
+        .prefixes(&filter, query.aggregate)
+    {
+        Some(prefixes) => Ok(Json(prefixes)),
+        // None of the providers can answer the reverse lookup
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_asn_prefixes(
+    Path(asn): Path<String>,
+    Query(query): Query<PrefixesQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<PrefixesResponse>, StatusCode> {
+    match parse_asn(&asn) {
+        Some(asn) => prefixes(state, PrefixFilter::Asn(asn), query).await,
+        None => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_country_prefixes(
+    Path(country): Path<String>,
+    Query(query): Query<PrefixesQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<PrefixesResponse>, StatusCode> {
+    let country = country.trim();
+    if country.len() != 2 || !country.chars().all(|x| x.is_ascii_alphabetic()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    prefixes(
+        state,
+        PrefixFilter::Country(country.to_ascii_uppercase()),
+        query,
+    )
+    .await
+}
+
+async fn get_rir_prefixes(
+    Path(rir): Path<String>,
+    Query(query): Query<PrefixesQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<PrefixesResponse>, StatusCode> {
+    match Rir::from_str(rir.trim()) {
+        Ok(rir) => prefixes(state, PrefixFilter::Rir(rir), query).await,
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_holder(
+    Path(opaque_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<HolderResponse>, StatusCode> {
+    match state.providers.read().await.holder(opaque_id.trim()) {
+        Some(holder) => Ok(Json(holder)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Addresses are looked up in chunks, so that the providers aren't locked for the whole request
+const LOOKUP_CHUNK_SIZE: usize = 1024;
+const LOOKUP_MAX_ADDRESSES: usize = 65536;
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
 
-            for entry in state.providers.read().await.stats.values.iter() {
-                if entry.cidr.address_is_in(address_bits) {
-                    return Ok(Json(ValueResponse {
-                        value: entry.country.to_owned(),
-                    }));
+async fn post_lookup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<LookupRequest>,
+) -> Result<Response, StatusCode> {
+    let addresses = match request {
+        LookupRequest::Addresses(addresses) => {
+            if addresses.len() > LOOKUP_MAX_ADDRESSES {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            addresses
+        }
+        LookupRequest::Cidr(cidr) => match CIDR::from_str(cidr.trim()) {
+            Ok(cidr) => match (cidr.first_address(), cidr.last_address()) {
+                (IpAddr::V4(first), IpAddr::V4(last)) => {
+                    if last.to_bits() - first.to_bits() >= LOOKUP_MAX_ADDRESSES as u32 {
+                        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                    }
+                    (first.to_bits()..=last.to_bits())
+                        .map(|address| IpAddr::V4(Ipv4Addr::from_bits(address)))
+                        .collect::<Vec<_>>()
+                }
+                (IpAddr::V6(first), IpAddr::V6(last)) => {
+                    if last.to_bits() - first.to_bits() >= LOOKUP_MAX_ADDRESSES as u128 {
+                        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                    }
+                    (first.to_bits()..=last.to_bits())
+                        .map(|address| IpAddr::V6(Ipv6Addr::from_bits(address)))
+                        .collect::<Vec<_>>()
+                }
+                _ => return Err(StatusCode::BAD_REQUEST),
+            },
+            Err(_) => return Err(StatusCode::BAD_REQUEST),
+        },
+    };
+
+    let ndjson = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON_CONTENT_TYPE));
+
+    let chunks = addresses
+        .chunks(LOOKUP_CHUNK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect::<Vec<_>>();
+    let providers = state.providers.clone();
+    let results = stream::iter(chunks.into_iter().enumerate()).then(move |(i, chunk)| {
+        let providers = providers.clone();
+        async move {
+            let providers = providers.read().await;
+            let mut buffer = Vec::new();
+
+            for (j, address) in chunk.into_iter().enumerate() {
+                if !ndjson && (i > 0 || j > 0) {
+                    buffer.push(b',');
+                }
+                serde_json::to_writer(
+                    &mut buffer,
+                    &LookupResponse {
+                        address,
+                        details: providers.address(address),
+                    },
+                )
+                .unwrap();
+                if ndjson {
+                    buffer.push(b'\n');
                 }
             }
 
-            Ok(Json(ValueResponse { value: None }))
+            Ok::<_, Infallible>(buffer)
         }
-        Err(_) => Err(StatusCode::BAD_REQUEST),
+    });
+
+    let response = Response::builder().header(
+        header::CONTENT_TYPE,
+        match ndjson {
+            true => NDJSON_CONTENT_TYPE,
+            false => "application/json",
+        },
+    );
+
+    Ok(match ndjson {
+        true => response.body(Body::from_stream(results)),
+        false => response.body(Body::from_stream(
+            stream::once(async { Ok(b"[".to_vec()) })
+                .chain(results)
+                .chain(stream::once(async { Ok(b"]".to_vec()) })),
+        )),
+    }
+    .unwrap())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SourceStatusResponse {
+    filepath: String,
+    url: Option<String>,
+    max_time: u32,
+    /// Unix timestamp of the last modification of the file, missing when the file doesn't exist
+    modified: Option<u64>,
+    /// Seconds since the last modification
+    age: Option<u64>,
+    /// Whether the file is missing or older than max_time
+    stale: bool,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SkippedLineResponse {
+    filepath: String,
+    line: usize,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProviderStatusResponse {
+    id: String,
+    entries: usize,
+    /// Unix timestamp of the last successful (re)load
+    loaded_at: Option<u64>,
+    load_duration_ms: Option<u64>,
+    /// Whether the provider was restored from the snapshot instead of being parsed
+    from_snapshot: bool,
+    last_error: Option<String>,
+    /// Number of the malformed lines skipped by the last successful (re)load
+    skipped_lines: usize,
+    /// The first few of the skipped lines
+    skipped: Vec<SkippedLineResponse>,
+    sources: Vec<SourceStatusResponse>,
+}
+
+fn unix_timestamp(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+async fn get_providers(State(state): State<AppState>) -> Json<Vec<ProviderStatusResponse>> {
+    let mut statuses = Vec::new();
+
+    // The files are checked only after the providers have been unlocked
+    let providers = {
+        let providers = state.providers.read().await;
+        providers
+            .ids()
+            .into_iter()
+            .map(|id| {
+                (
+                    providers.sources(&id).cloned().unwrap_or_default(),
+                    providers.stats(&id).unwrap_or_default(),
+                    providers.status(&id).cloned().unwrap_or_default(),
+                    providers.skipped(&id).cloned().unwrap_or_default(),
+                    id,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for (sources, stats, status, skipped, id) in providers {
+        let mut source_statuses = Vec::new();
+        for source in sources {
+            let modified = tokio::fs::metadata(&source.filepath)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            // Same as for the scheduler, the file is as fresh as its last download or check
+            let last_checked = download::last_checked(&source).await;
+            let checked = modified
+                .map(|modified| last_checked.map_or(modified, |checked| checked.max(modified)));
+            let age = checked
+                .and_then(|checked| SystemTime::now().duration_since(checked).ok())
+                .map(|age| age.as_secs());
+
+            source_statuses.push(SourceStatusResponse {
+                stale: age.is_none_or(|age| age > source.max_time as u64),
+                modified: modified.and_then(unix_timestamp),
+                age,
+                last_error: status.source_errors.get(&source.filepath).cloned(),
+                filepath: source.filepath,
+                url: source.url,
+                max_time: source.max_time,
+            });
+        }
+
+        statuses.push(ProviderStatusResponse {
+            id,
+            entries: stats.entries,
+            loaded_at: status.loaded_at.and_then(unix_timestamp),
+            load_duration_ms: status
+                .load_duration
+                .map(|duration| duration.as_millis() as u64),
+            from_snapshot: status.from_snapshot,
+            last_error: status.last_error,
+            skipped_lines: skipped.count,
+            skipped: skipped
+                .lines
+                .into_iter()
+                .map(|x| SkippedLineResponse {
+                    filepath: x.filepath,
+                    line: x.line,
+                    reason: x.reason,
+                })
+                .collect(),
+            sources: source_statuses,
+        });
     }
+
+    Json(statuses)
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    kind: Option<String>,
+}
+
+async fn get_report(
+    State(state): State<AppState>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<Report>, StatusCode> {
+    let kind = match query.kind.as_deref().map(InconsistencyKind::from_str) {
+        Some(Ok(kind)) => Some(kind),
+        Some(Err(_)) => return Err(StatusCode::BAD_REQUEST),
+        None => None,
+    };
+
+    // The whole address space is walked, so the lookups are done outside of the async runtime and
+    // on a clone of the providers, which doesn't keep the reloads (and the lookups behind them) waiting
+    let providers = state.providers.read().await.clone();
+    tokio::task::spawn_blocking(move || Json(report::build(&providers, kind)))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    since: String,
+}
+
+async fn get_changes(
+    State(state): State<AppState>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesResponse>, StatusCode> {
+    let day = utils::parse_date(&query.since).ok_or(StatusCode::BAD_REQUEST)?;
+
+    // The archived versions are parsed without holding the lock, same as on reloads, and they're
+    // compared with a clone of the providers, same as for the report
+    let archived = Providers::load_archived(&state.providers, day).await;
+    let providers = state.providers.read().await.clone();
+    tokio::task::spawn_blocking(move || Json(changes::build(&providers, &archived)))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 #[derive(Serialize)]
@@ -198,38 +551,62 @@ impl GetJWTKeys for AppState {
     }
 }
 
-pub async fn run(
-    settings: Arc<Settings>,
-    unit_uuid: Arc<Option<Uuid>>,
-    jwt_keys: Option<Arc<JWTKeys>>,
-    providers: Arc<RwLock<Providers>>,
-) {
-    let state = AppState {
-        unit_uuid,
-        jwt_keys,
-        providers,
-    };
-
-    let mut address_router = Router::new()
+fn router(state: AppState, auth: bool) -> Router {
+    let address_router = Router::new()
+        .route("/", get(get_address))
         .route("/allocation", get(get_allocation))
         .route("/rir", get(get_rir))
         .route("/asn", get(get_asn))
-        .route("/country", get(get_country));
+        .route("/country", get(get_country))
+        .route("/geo", get(get_geo))
+        .route("/routed", get(get_routed))
+        .route("/rpki", get(get_rpki));
+
+    let asn_router = Router::new()
+        .route("/{asn}", get(get_autsys))
+        .route("/{asn}/name", get(get_autsys_name))
+        .route("/{asn}/prefixes", get(get_asn_prefixes));
 
-    if settings.api.auth {
-        address_router = address_router.layer(middleware::from_fn_with_state(
+    let mut authenticated_router = Router::new()
+        .route("/_providers", get(get_providers))
+        .route("/_report", get(get_report))
+        .route("/changes", get(get_changes))
+        .route("/lookup", post(post_lookup))
+        .nest("/asn", asn_router)
+        .route("/country/{country}/prefixes", get(get_country_prefixes))
+        .route("/rir/{rir}/prefixes", get(get_rir_prefixes))
+        .route("/holder/{opaque_id}", get(get_holder))
+        .nest("/{address}", address_router);
+
+    // Only the routes above need a token, the unit's info and health are public
+    if auth {
+        authenticated_router = authenticated_router.route_layer(middleware::from_fn_with_state(
             state.clone(),
             mtilib::auth::axum_middleware::<AppState>,
-        ))
+        ));
     }
 
-    let app = Router::new()
+    Router::new()
         .route("/", get(index))
         .route("/_unit", get(unit))
         .route("/_health", get(health))
-        .nest("/{address}", address_router)
+        .merge(authenticated_router)
         .with_state(state)
-        .layer(TraceLayer::new_for_http());
+        .layer(TraceLayer::new_for_http())
+}
+
+pub async fn run(
+    settings: Arc<Settings>,
+    unit_uuid: Arc<Option<Uuid>>,
+    jwt_keys: Option<Arc<JWTKeys>>,
+    providers: Arc<RwLock<Providers>>,
+) {
+    let state = AppState {
+        unit_uuid,
+        jwt_keys,
+        providers,
+    };
+    let app = router(state, settings.api.auth);
 
     let listener = tokio::net::TcpListener::bind(SocketAddr::new(
         IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
@@ -241,3 +618,427 @@ pub async fn run(
     info!("Listening on port {}!", settings.api.port);
     axum::serve(listener, app).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use axum::{
+        body::{self, Body},
+        http::{header, Request, StatusCode},
+    };
+    use mtilib::types::{
+        AddressResponse, AllocationState, AsnNameResponse, AsnResponse, ChangesResponse,
+        LookupResponse, PrefixesResponse, Rir, RpkiResponse, RpkiState,
+    };
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    use super::{router, AppState, ProviderStatusResponse, NDJSON_CONTENT_TYPE};
+    use crate::{
+        providers::{
+            thyme::as_names::{AsNameEntry, AsNamesProvider},
+            Provider, Providers,
+        },
+        report::{InconsistencyKind, Report},
+    };
+
+    async fn test_state() -> AppState {
+        AppState {
+            unit_uuid: Arc::new(None),
+            jwt_keys: None,
+            providers: Arc::new(RwLock::new(Providers::fixture().await)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_address() {
+        let response = router(test_state().await, false)
+            .oneshot(Request::get("/1.1.1.1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let address: AddressResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(address.allocation_state, AllocationState::Allocated);
+        assert_eq!(address.rir, Some(Rir::Apnic));
+        assert_eq!(address.top_rir, None);
+        assert_eq!(address.asn, Some(13335));
+        assert_eq!(address.country, Some(String::from("AU")));
+        assert_eq!(address.rpki, Some(RpkiState::Valid));
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let app = router(test_state().await, true);
+        for uri in ["/_unit", "/_health"] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_ne!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+        for uri in [
+            "/1.1.1.1",
+            "/1.1.1.1/asn",
+            "/asn/13335",
+            "/changes",
+            "/_providers",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_rpki() {
+        let app = router(test_state().await, false);
+
+        for (address, state, prefix) in [
+            ("1.1.1.1", RpkiState::Valid, Some("1.1.1.0/24")),
+            ("2001:db8::1", RpkiState::Invalid, Some("2001:db8::/32")),
+            ("8.8.8.8", RpkiState::NotFound, None),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get(format!("/{}/rpki", address))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let rpki: RpkiResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(rpki.state, state);
+            assert_eq!(rpki.prefix.as_deref(), prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_prefixes() {
+        let app = router(test_state().await, false);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/asn/AS13335/prefixes")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let prefixes: PrefixesResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(prefixes.prefixes, vec![String::from("1.1.1.0/24")]);
+        assert_eq!(prefixes.ipv4_addresses, 256);
+        assert_eq!(prefixes.ipv6_addresses, 0);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/country/au/prefixes?aggregate=true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let prefixes: PrefixesResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(prefixes.prefixes, vec![String::from("1.0.0.0/8")]);
+
+        for (uri, status) in [
+            ("/asn/foo/prefixes", StatusCode::BAD_REQUEST),
+            ("/country/AUS/prefixes", StatusCode::BAD_REQUEST),
+            ("/rir/foo/prefixes", StatusCode::BAD_REQUEST),
+            // None of the test providers know the RIR allocations
+            ("/rir/apnic/prefixes", StatusCode::NOT_FOUND),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_lookup() {
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::post("/lookup")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"cidr": "1.1.0.255/23"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let results: Vec<LookupResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results.len(), 512);
+        assert_eq!(results[0].address, IpAddr::V4(Ipv4Addr::new(1, 1, 0, 0)));
+        assert_eq!(results[0].details.asn, None);
+        assert_eq!(results[257].address, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(results[257].details.asn, Some(13335));
+    }
+
+    #[tokio::test]
+    async fn test_post_lookup_ndjson() {
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::post("/lookup")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::ACCEPT, NDJSON_CONTENT_TYPE)
+                    .body(Body::from(r#"{"addresses": ["1.1.1.1", "2.2.2.2"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let results = body
+            .split(|x| *x == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice::<LookupResponse>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].details.country, Some(String::from("AU")));
+        assert_eq!(
+            results[1].details.allocation_state,
+            AllocationState::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_lookup_too_large() {
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::post("/lookup")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"cidr": "1.0.0.0/8"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_get_address_ipv6() {
+        let response = router(test_state().await, false)
+            .oneshot(Request::get("/2001:db8::1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let address: AddressResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(address.asn, Some(64496));
+    }
+
+    #[tokio::test]
+    async fn test_get_autsys() {
+        let app = router(test_state().await, false);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/asn/AS13335").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let autsys: AsnResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(autsys.rir, Rir::Arin);
+        assert_eq!(autsys.country, Some(String::from("US")));
+
+        let response = app
+            .oneshot(Request::get("/asn/13336").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_autsys_name() {
+        let state = test_state().await;
+        let mut names = AsNamesProvider::load_sources(vec![]).await.unwrap();
+        names.values.push(AsNameEntry {
+            asn: 13335,
+            name: String::from("CLOUDFLARENET - Cloudflare, Inc."),
+            country: Some(String::from("US")),
+        });
+        state
+            .providers
+            .write()
+            .await
+            .insert("thyme.as_names", names);
+        let app = router(state, false);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/asn/AS13335/name")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let name: AsnNameResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(name.name, "CLOUDFLARENET - Cloudflare, Inc.");
+        assert_eq!(name.country, Some(String::from("US")));
+
+        // The addresses come with the name of their origin
+        let response = app
+            .clone()
+            .oneshot(Request::get("/1.1.1.1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let address: AddressResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(address.asn_name, Some(name));
+
+        let response = app
+            .oneshot(Request::get("/asn/64496/name").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_providers() {
+        let response = router(test_state().await, false)
+            .oneshot(Request::get("/_providers").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let statuses: Vec<ProviderStatusResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(statuses.len(), 3);
+
+        let rpki = statuses.iter().find(|x| x.id == "rpki.roas").unwrap();
+        assert_eq!(rpki.entries, 2);
+        assert_eq!(rpki.sources.len(), 1);
+        assert!(rpki.sources[0].modified.is_some());
+        assert!(!rpki.sources[0].stale);
+        assert_eq!(rpki.sources[0].last_error, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_report() {
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::get("/_report?kind=rir_mismatch")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let report: Report = serde_json::from_slice(&body).unwrap();
+        assert!(report
+            .inconsistencies
+            .iter()
+            .all(|x| x.kind == InconsistencyKind::RirMismatch));
+
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::get("/_report?kind=bogus")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_changes() {
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::get("/changes?since=2024-01-31")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Nothing is archived in the tests, so nothing can be compared
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let changes: ChangesResponse = serde_json::from_slice(&body).unwrap();
+        assert!(changes.archived.is_none());
+        assert!(changes.changes.is_empty());
+        assert!(changes.missing.contains(&String::from("arin.stats")));
+
+        let response = router(test_state().await, false)
+            .oneshot(
+                Request::get("/changes?since=2024-02-31")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_address_bad_request() {
+        let response = router(test_state().await, false)
+            .oneshot(Request::get("/1.1.1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
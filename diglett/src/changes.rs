@@ -0,0 +1,241 @@
+// Changes of the registry and routing data since a date
+//
+// Each provider is compared with its version parsed from the archived sources which were in use on
+// the date. Same as in the report, the address space is split into segments at the boundaries of the
+// ranges of both versions, so that each of them gives the same answer for the whole segment, and the
+// neighbouring segments with the same change are merged back together before being split into prefixes.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use mtilib::types::{ChangesResponse, PrefixChange, PrefixState};
+
+use crate::{
+    providers::{ArchivedProviders, LookupKind, LookupValue, Provider, Providers},
+    utils,
+};
+
+/// What the provider says about the address, the RIR is the delegating one when the provider knows it.
+pub fn state(provider: &dyn Provider, address: IpAddr) -> PrefixState {
+    PrefixState {
+        allocation_state: match provider.lookup(LookupKind::AllocationState, address) {
+            Some(LookupValue::AllocationState(state)) => Some(state),
+            _ => None,
+        },
+        rir: match provider
+            .lookup(LookupKind::Rir, address)
+            .or_else(|| provider.lookup(LookupKind::TopRir, address))
+        {
+            Some(LookupValue::Rir(rir)) => Some(rir),
+            _ => None,
+        },
+        country: match provider.lookup(LookupKind::Country, address) {
+            Some(LookupValue::Country(country)) => Some(country),
+            _ => None,
+        },
+        asn: match provider.lookup(LookupKind::Asn, address) {
+            Some(LookupValue::Asn(asn)) => Some(asn),
+            _ => None,
+        },
+    }
+}
+
+struct Segment {
+    start: u128,
+    end: u128,
+    before: PrefixState,
+    after: PrefixState,
+}
+
+// The changed segments between the sorted boundaries, the last one reaches to the end of the address space
+fn walk(
+    before: &dyn Provider,
+    after: &dyn Provider,
+    boundaries: Vec<u128>,
+    max: u128,
+    address: impl Fn(u128) -> IpAddr,
+) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for (i, start) in boundaries.iter().enumerate() {
+        let end = boundaries.get(i + 1).map_or(max, |next| next - 1);
+        let old = state(before, address(*start));
+        let new = state(after, address(*start));
+        if old == new {
+            continue;
+        }
+
+        match segments.last_mut() {
+            Some(last) if last.end + 1 == *start && last.before == old && last.after == new => {
+                last.end = end
+            }
+            _ => segments.push(Segment {
+                start: *start,
+                end,
+                before: old,
+                after: new,
+            }),
+        }
+    }
+
+    segments
+}
+
+/// The prefixes whose state changed between the two versions of the provider, in the order of their addresses.
+pub fn compare(id: &str, before: &dyn Provider, after: &dyn Provider) -> Vec<PrefixChange> {
+    let mut v4_boundaries = Vec::new();
+    let mut v6_boundaries = Vec::new();
+    for range in [before.ranges(), after.ranges()]
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        match range {
+            (IpAddr::V4(start), IpAddr::V4(end)) => {
+                v4_boundaries.push(start.to_bits() as u128);
+                v4_boundaries.extend(end.to_bits().checked_add(1).map(|x| x as u128));
+            }
+            (IpAddr::V6(start), IpAddr::V6(end)) => {
+                v6_boundaries.push(start.to_bits());
+                v6_boundaries.extend(end.to_bits().checked_add(1));
+            }
+            _ => {}
+        }
+    }
+    for boundaries in [&mut v4_boundaries, &mut v6_boundaries] {
+        boundaries.sort_unstable();
+        boundaries.dedup();
+    }
+
+    let v4_address = |x: u128| IpAddr::V4(Ipv4Addr::from_bits(x as u32));
+    let v6_address = |x: u128| IpAddr::V6(Ipv6Addr::from_bits(x));
+    let v4 = walk(before, after, v4_boundaries, u32::MAX as u128, v4_address)
+        .into_iter()
+        .map(|segment| (v4_address(segment.start), v4_address(segment.end), segment));
+    let v6 = walk(before, after, v6_boundaries, u128::MAX, v6_address)
+        .into_iter()
+        .map(|segment| (v6_address(segment.start), v6_address(segment.end), segment));
+
+    v4.chain(v6)
+        .flat_map(|(start, end, segment)| {
+            utils::range_prefixes(start, end)
+                .into_iter()
+                .map(move |prefix| PrefixChange {
+                    prefix: prefix.to_string(),
+                    provider: id.to_string(),
+                    before: segment.before.clone(),
+                    after: segment.after.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Compares the archived versions of the providers with the current ones.
+pub fn build(providers: &Providers, archived: &ArchivedProviders) -> ChangesResponse {
+    let mut changes = Vec::new();
+    for entry in archived.providers.iter() {
+        if let Some(provider) = providers.get(&entry.id) {
+            changes.extend(compare(&entry.id, entry.provider.as_ref(), provider));
+        }
+    }
+
+    ChangesResponse {
+        archived: archived
+            .providers
+            .iter()
+            .map(|entry| entry.day)
+            .min()
+            .map(utils::format_date),
+        missing: archived.missing.clone(),
+        changes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use mtilib::types::{AllocationState, Rir};
+
+    use super::compare;
+    use crate::{
+        providers::{
+            stats::{StatsEntry, StatsProvider},
+            CidrIndex, Provider,
+        },
+        utils::CIDR,
+    };
+
+    async fn stats(entries: &[(&str, AllocationState, Rir, &str)]) -> StatsProvider {
+        let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+        for (cidr, allocation_state, rir, country) in entries {
+            stats.values.push(StatsEntry {
+                cidr: CIDR::from_str(cidr).unwrap(),
+                allocation_state: allocation_state.clone(),
+                rir: rir.clone(),
+                country: Some(country.to_string()),
+                registration_date: None,
+                opaque_id: None,
+            });
+        }
+        stats.index = CidrIndex::build(stats.values.iter().map(|x| &x.cidr));
+        stats
+    }
+
+    #[tokio::test]
+    async fn test_compare() {
+        let before = stats(&[
+            ("1.0.0.0/24", AllocationState::Allocated, Rir::Apnic, "AU"),
+            ("1.0.1.0/24", AllocationState::Allocated, Rir::Apnic, "AU"),
+            (
+                "2.0.0.0/16",
+                AllocationState::Unallocated,
+                Rir::Ripencc,
+                "ZZ",
+            ),
+            ("2001:db8::/32", AllocationState::Allocated, Rir::Arin, "US"),
+        ])
+        .await;
+        let after = stats(&[
+            ("1.0.0.0/24", AllocationState::Allocated, Rir::Apnic, "AU"),
+            ("1.0.1.0/24", AllocationState::Allocated, Rir::Apnic, "JP"),
+            ("2.0.0.0/17", AllocationState::Allocated, Rir::Ripencc, "FR"),
+            (
+                "2.0.128.0/17",
+                AllocationState::Allocated,
+                Rir::Ripencc,
+                "FR",
+            ),
+        ])
+        .await;
+
+        let changes = compare("arin.stats", &before, &after);
+        let found = changes
+            .iter()
+            .map(|x| {
+                (
+                    x.prefix.as_str(),
+                    x.before.country.as_deref(),
+                    x.after.country.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The unchanged 1.0.0.0/24 is left out and the two halves with the same change are merged
+        assert_eq!(
+            found,
+            vec![
+                ("1.0.1.0/24", Some("AU"), Some("JP")),
+                ("2.0.0.0/16", Some("ZZ"), Some("FR")),
+                ("2001:db8::/32", Some("US"), None),
+            ]
+        );
+        assert_eq!(
+            changes[1].before.allocation_state,
+            Some(AllocationState::Unallocated)
+        );
+        assert_eq!(
+            changes[1].after.allocation_state,
+            Some(AllocationState::Allocated)
+        );
+        assert!(changes.iter().all(|x| x.provider == "arin.stats"));
+        assert!(compare("arin.stats", &after, &after).is_empty());
+    }
+}
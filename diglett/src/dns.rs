@@ -0,0 +1,398 @@
+// Authoritative DNS server (UDP) with the Team Cymru style TXT lookups
+//
+// `<reversed-ipv4>.origin.<zone>` and `<reversed-ipv6-nibbles>.origin6.<zone>` are answered with
+// "ASN | prefix | country | RIR | date" of the routed prefix and `AS<n>.<zone>` with
+// "ASN | country | RIR | date" of the AS number. Only the TXT (and ANY) queries get an answer.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::{net::UdpSocket, sync::RwLock};
+use tracing::{error, info};
+
+use crate::{providers::Providers, settings::Settings};
+
+const HEADER_LENGTH: usize = 12;
+// Longer answers get the truncated flag, there is no TCP listener to retry with
+const MAX_UDP_LENGTH: usize = 512;
+const MAX_QUERY_LENGTH: usize = 4096;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+const OPCODE_QUERY: u16 = 0;
+
+const RCODE_NOERROR: u16 = 0;
+const RCODE_FORMERR: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+const RCODE_NOTIMP: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+const TYPE_TXT: u16 = 16;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+// Pointer to the name of the question, which always starts right after the header
+const NAME_POINTER: [u8; 2] = [0xc0, HEADER_LENGTH as u8];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DnsQuery {
+    Origin(IpAddr),
+    Autsys(u32),
+}
+
+struct Question<'a> {
+    labels: Vec<String>,
+    kind: u16,
+    class: u16,
+    // The question section as received, echoed back in the response
+    raw: &'a [u8],
+}
+
+fn parse_question(packet: &[u8]) -> Option<Question<'_>> {
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LENGTH;
+    loop {
+        let length = *packet.get(pos)? as usize;
+        pos += 1;
+        match length {
+            0 => break,
+            // Compression pointers aren't used in the questions
+            1..=63 => {
+                let label = packet.get(pos..pos + length)?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                pos += length;
+            }
+            _ => return None,
+        }
+    }
+
+    let fields = packet.get(pos..pos + 4)?;
+    Some(Question {
+        labels,
+        kind: u16::from_be_bytes([fields[0], fields[1]]),
+        class: u16::from_be_bytes([fields[2], fields[3]]),
+        raw: &packet[HEADER_LENGTH..pos + 4],
+    })
+}
+
+/// Parses the labels in front of the zone, `None` when the name isn't a lookup.
+pub fn parse_name(labels: &[String]) -> Option<DnsQuery> {
+    let (kind, address) = labels.split_last()?;
+
+    match kind.as_str() {
+        // Any number of octets, the missing ones are zero
+        "origin" if (1..=4).contains(&address.len()) => {
+            let mut octets = [0; 4];
+            for (i, label) in address.iter().rev().enumerate() {
+                octets[i] = label.parse().ok()?;
+            }
+            Some(DnsQuery::Origin(IpAddr::V4(Ipv4Addr::from(octets))))
+        }
+        // Any number of nibbles, the missing ones are zero
+        "origin6" if (1..=32).contains(&address.len()) => {
+            let mut bits = 0u128;
+            for (i, label) in address.iter().rev().enumerate() {
+                if label.len() != 1 {
+                    return None;
+                }
+                let nibble = u128::from_str_radix(label, 16).ok()?;
+                bits |= nibble << (124 - 4 * i);
+            }
+            Some(DnsQuery::Origin(IpAddr::V6(Ipv6Addr::from_bits(bits))))
+        }
+        _ if address.is_empty() => match kind.get(..2) {
+            Some("as") => kind[2..].parse().ok().map(DnsQuery::Autsys),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The TXT record of the lookup, `None` when there is nothing to answer with (NXDOMAIN).
+pub fn txt(providers: &Providers, query: &DnsQuery) -> Option<String> {
+    match query {
+        DnsQuery::Origin(address) => {
+            let routed = providers.routed(*address);
+            let prefix = routed.prefix?;
+            let info = providers.address(*address);
+
+            Some(format!(
+                "{} | {} | {} | {} | {}",
+                routed
+                    .origins
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                prefix,
+                info.country.unwrap_or_default(),
+                info.rir
+                    .or(info.top_rir)
+                    .map(|rir| rir.id().to_string())
+                    .unwrap_or_default(),
+                info.registration_date.unwrap_or_default(),
+            ))
+        }
+        DnsQuery::Autsys(asn) => {
+            let autsys = providers.autsys(*asn)?;
+            let mut text = format!(
+                "{} | {} | {} | {}",
+                asn,
+                autsys.country.unwrap_or_default(),
+                autsys.rir.id(),
+                autsys.registration_date.unwrap_or_default(),
+            );
+
+            // Same as in the list the name comes from, "<name>, <country>"
+            if let Some(name) = providers.autsys_name(*asn) {
+                text.push_str(" | ");
+                text.push_str(&name.name);
+                if let Some(country) = name.country {
+                    text.push_str(", ");
+                    text.push_str(&country);
+                }
+            }
+
+            Some(text)
+        }
+    }
+}
+
+fn txt_record(ttl: u32, text: &str) -> Vec<u8> {
+    // The text is split into character strings of at most 255 bytes
+    let mut rdata = Vec::new();
+    for chunk in text.as_bytes().chunks(255) {
+        rdata.push(chunk.len() as u8);
+        rdata.extend_from_slice(chunk);
+    }
+
+    let mut record = Vec::from(NAME_POINTER);
+    record.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    record.extend_from_slice(&CLASS_IN.to_be_bytes());
+    record.extend_from_slice(&ttl.to_be_bytes());
+    record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    record.append(&mut rdata);
+    record
+}
+
+/// Answers a single DNS query packet, `None` when the packet should be dropped.
+pub fn answer(providers: &Providers, zone: &str, ttl: u32, packet: &[u8]) -> Option<Vec<u8>> {
+    let header = packet.get(..HEADER_LENGTH)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+
+    let response = |rcode: u16, question: Option<&[u8]>, record: Option<Vec<u8>>| {
+        let mut flags = FLAG_RESPONSE | (flags & 0x7800) | (flags & FLAG_RECURSION_DESIRED) | rcode;
+        if rcode != RCODE_REFUSED && rcode != RCODE_FORMERR {
+            flags |= FLAG_AUTHORITATIVE;
+        }
+
+        let mut response = Vec::from(&header[..2]);
+        response.extend_from_slice(&flags.to_be_bytes());
+        response.extend_from_slice(&(question.is_some() as u16).to_be_bytes());
+        response.extend_from_slice(&(record.is_some() as u16).to_be_bytes());
+        response.extend_from_slice(&[0; 4]);
+        response.extend_from_slice(question.unwrap_or_default());
+        if let Some(record) = record {
+            response.extend(record);
+        }
+
+        if response.len() > MAX_UDP_LENGTH {
+            response.truncate(HEADER_LENGTH + question.map_or(0, |x| x.len()));
+            response[7] = 0;
+            let flags = flags | FLAG_TRUNCATED;
+            response[2..4].copy_from_slice(&flags.to_be_bytes());
+        }
+        Some(response)
+    };
+
+    if (flags >> 11) & 0xf != OPCODE_QUERY {
+        return response(RCODE_NOTIMP, None, None);
+    }
+    let question = match parse_question(packet) {
+        Some(question) if question_count == 1 => question,
+        _ => return response(RCODE_FORMERR, None, None),
+    };
+
+    // Only the names within the zone are answered, this is not a resolver
+    let zone_labels = zone.split('.').filter(|x| !x.is_empty()).count();
+    let in_zone = question.labels.len() >= zone_labels
+        && question.labels[question.labels.len() - zone_labels..].join(".") == zone;
+    if !in_zone || !matches!(question.class, CLASS_IN | CLASS_ANY) {
+        return response(RCODE_REFUSED, Some(question.raw), None);
+    }
+
+    let labels = &question.labels[..question.labels.len() - zone_labels];
+    let text = match parse_name(labels).and_then(|query| txt(providers, &query)) {
+        Some(text) => text,
+        // The zone itself exists, it just doesn't have any records
+        None if labels.is_empty() => return response(RCODE_NOERROR, Some(question.raw), None),
+        None => return response(RCODE_NXDOMAIN, Some(question.raw), None),
+    };
+
+    match question.kind {
+        TYPE_TXT | TYPE_ANY => response(
+            RCODE_NOERROR,
+            Some(question.raw),
+            Some(txt_record(ttl, &text)),
+        ),
+        _ => response(RCODE_NOERROR, Some(question.raw), None),
+    }
+}
+
+pub async fn serve(socket: UdpSocket, providers: Arc<RwLock<Providers>>, zone: String, ttl: u32) {
+    let mut buffer = [0; MAX_QUERY_LENGTH];
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(error) => {
+                error!("Failed to receive a DNS query! ({})", error);
+                continue;
+            }
+        };
+
+        let response = answer(&*providers.read().await, &zone, ttl, &buffer[..length]);
+        if let Some(response) = response {
+            if let Err(error) = socket.send_to(&response, peer).await {
+                error!("Failed to answer the DNS query from {}! ({})", peer, error);
+            }
+        }
+    }
+}
+
+pub async fn run(settings: Arc<Settings>, providers: Arc<RwLock<Providers>>) {
+    let dns = settings
+        .dns
+        .as_ref()
+        .expect("Invalid config (dns section is missing)!");
+
+    let socket = UdpSocket::bind(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        dns.port,
+    ))
+    .await
+    .unwrap();
+
+    // The names are compared in lowercase and without the trailing dot
+    let zone = dns.zone.trim_end_matches('.').to_ascii_lowercase();
+
+    info!("DNS listening on port {} for {}!", dns.port, zone);
+    serve(socket, providers, zone, dns.ttl).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        str::FromStr,
+    };
+
+    use super::{answer, parse_name, txt, DnsQuery};
+    use crate::providers::Providers;
+
+    fn labels(name: &str) -> Vec<String> {
+        name.split('.').map(String::from).collect()
+    }
+
+    fn query_packet(name: &str, kind: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&kind.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(
+            parse_name(&labels("4.3.2.1.origin")),
+            Some(DnsQuery::Origin(IpAddr::from_str("1.2.3.4").unwrap()))
+        );
+        assert_eq!(
+            parse_name(&labels("2.1.origin")),
+            Some(DnsQuery::Origin(IpAddr::V4(Ipv4Addr::new(1, 2, 0, 0))))
+        );
+        assert_eq!(
+            parse_name(&labels("1.0.0.0.8.b.d.0.1.0.0.2.origin6")),
+            Some(DnsQuery::Origin(IpAddr::from_str("2001:db8:1::").unwrap()))
+        );
+        assert_eq!(
+            parse_name(&labels("as13335")),
+            Some(DnsQuery::Autsys(13335))
+        );
+        assert_eq!(parse_name(&labels("256.1.origin")), None);
+        assert_eq!(parse_name(&labels("10.origin6")), None);
+        assert_eq!(parse_name(&labels("foo.as13335")), None);
+    }
+
+    #[tokio::test]
+    async fn test_txt() {
+        let providers = Providers::fixture().await;
+
+        assert_eq!(
+            txt(
+                &providers,
+                &DnsQuery::Origin(IpAddr::from_str("1.1.1.1").unwrap())
+            ),
+            Some(String::from("13335 | 1.1.1.0/24 | AU | apnic | 2011-08-11"))
+        );
+        assert_eq!(
+            txt(&providers, &DnsQuery::Autsys(13335)),
+            Some(String::from("13335 | US | arin | 2010-07-14"))
+        );
+        assert_eq!(
+            txt(
+                &providers,
+                &DnsQuery::Origin(IpAddr::from_str("1.2.3.4").unwrap())
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answer() {
+        let providers = Providers::fixture().await;
+        let zone = "asn.example.com";
+
+        let query = query_packet("1.1.1.1.origin.ASN.example.com", 16);
+        let response = answer(&providers, zone, 300, &query).unwrap();
+        // Same id, a response with the authoritative flag and no error
+        assert_eq!(response[..2], [0x12, 0x34]);
+        assert_eq!(u16::from_be_bytes([response[2], response[3]]), 0x8500);
+        // One question (echoed back) and one answer
+        assert_eq!(response[4..8], [0, 1, 0, 1]);
+        assert_eq!(response[12..query.len()], query[12..]);
+        let text = b"13335 | 1.1.1.0/24 | AU | apnic | 2011-08-11";
+        assert_eq!(
+            response[response.len() - text.len() - 1] as usize,
+            text.len()
+        );
+        assert!(response.ends_with(text));
+
+        let query = query_packet("4.3.2.1.origin.asn.example.com", 16);
+        let response = answer(&providers, zone, 300, &query).unwrap();
+        assert_eq!(response[3] & 0xf, 3);
+
+        let query = query_packet("1.1.1.1.origin.example.org", 16);
+        let response = answer(&providers, zone, 300, &query).unwrap();
+        assert_eq!(response[3] & 0xf, 5);
+
+        // Responses are never answered
+        let mut query = query_packet("as13335.asn.example.com", 16);
+        query[2] |= 0x80;
+        assert!(answer(&providers, zone, 300, &query).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+// Import of pre-fetched source files (`diglett import <tarball>`)
+//
+// Used in environments without internet access, the tarball (optionally gzipped) contains the source
+// files, which get matched to the configured sources by their path or, when unambiguous, by their name.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use config::Config;
+use flate2::read::GzDecoder;
+use tracing::{error, info, warn};
+
+use crate::providers::{self, archive, ProviderSource};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Drops the "./" components, so that the paths from the config and the tarball can be compared
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+fn find_source<'a>(sources: &'a [ProviderSource], entry_path: &Path) -> Option<&'a ProviderSource> {
+    let entry_path = normalize(entry_path);
+
+    // The tarball may have been created from a different directory than the one diglett runs in
+    let by_path = sources.iter().find(|source| {
+        let filepath = normalize(Path::new(&source.filepath));
+        entry_path.ends_with(&filepath)
+            || (entry_path.components().count() > 1 && filepath.ends_with(&entry_path))
+    });
+    if by_path.is_some() {
+        return by_path;
+    }
+
+    let mut by_name = sources
+        .iter()
+        .filter(|source| Path::new(&source.filepath).file_name() == entry_path.file_name());
+    match (by_name.next(), by_name.next()) {
+        (Some(source), None) => Some(source),
+        _ => None,
+    }
+}
+
+fn import_entry(source: &ProviderSource, entry: &mut tar::Entry<impl Read>) -> io::Result<()> {
+    let filepath = Path::new(&source.filepath);
+    if let Some(prefix) = filepath.parent() {
+        fs::create_dir_all(prefix)?;
+    }
+
+    // Same as with the downloads, the file is swapped in only once it's complete
+    let temp_path = concat_string!(source.filepath, ".tmp");
+    let mut file = File::create(&temp_path)?;
+    io::copy(entry, &mut file)?;
+
+    // Keep the age of the file, so that max_time still means something
+    if let Ok(mtime) = entry.header().mtime() {
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))?;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    archive::archive_previous(source);
+    fs::rename(&temp_path, filepath)?;
+    archive::archive(source);
+
+    // The validators of the previous download don't apply to the imported file
+    let _ = fs::remove_file(concat_string!(source.filepath, ".meta"));
+
+    Ok(())
+}
+
+pub fn import(sources: &[ProviderSource], tarball: &Path) -> io::Result<usize> {
+    let mut file = BufReader::new(File::open(tarball)?);
+
+    let mut magic = [0; 2];
+    let gzipped = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.rewind()?;
+
+    let reader: Box<dyn Read> = match gzipped {
+        true => Box::new(GzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    let mut archive = tar::Archive::new(reader);
+
+    let mut imported = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry.path()?.into_owned();
+        match find_source(sources, &entry_path) {
+            Some(source) => {
+                import_entry(source, &mut entry)?;
+                info!(
+                    "Imported {} into {}!",
+                    entry_path.display(),
+                    source.filepath
+                );
+                imported.push(source.filepath.clone());
+            }
+            None => warn!(
+                "Skipping {}, it doesn't match any source!",
+                entry_path.display()
+            ),
+        }
+    }
+
+    for source in sources {
+        if !imported.contains(&source.filepath) {
+            warn!("Source {} was not in the tarball!", source.filepath);
+        }
+    }
+
+    Ok(imported.len())
+}
+
+pub async fn run(config: &Config, tarball: &str) {
+    let sources = providers::configured_providers(config)
+        .into_iter()
+        .filter_map(|id| providers::load_provider_sources(config, &id))
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let tarball = PathBuf::from(tarball);
+    match tokio::task::spawn_blocking(move || import(&sources, &tarball))
+        .await
+        .unwrap()
+    {
+        Ok(count) => info!("Imported {} source files!", count),
+        Err(error) => error!("Failed to import the source files! ({})", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::import;
+    use crate::providers::ProviderSource;
+
+    fn source(filepath: &Path) -> ProviderSource {
+        ProviderSource::local(filepath.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_import() {
+        let directory = std::env::temp_dir().join(format!("diglett-import-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let tarball = directory.join("sources.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&tarball).unwrap());
+        for (path, contents) in [
+            ("data/arin/stats/arin.txt", "arin\n"),
+            ("recovered.csv", "recovered\n"),
+            ("unknown.txt", "unknown\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(1_700_000_000);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let sources = [
+            source(&directory.join("data/arin/stats/arin.txt")),
+            source(&directory.join("data/iana/recovered.csv")),
+            source(&directory.join("data/iana/reserved.csv")),
+        ];
+        assert_eq!(import(&sources, &tarball).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&sources[0].filepath).unwrap(), "arin\n");
+        assert_eq!(
+            fs::read_to_string(&sources[1].filepath).unwrap(),
+            "recovered\n"
+        );
+        assert!(!Path::new(&sources[2].filepath).exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// Longest-prefix-match index used by the providers
+//
+// The prefixes are flattened into a sorted list of non-overlapping segments, where each segment
+// carries the value of the most specific prefix covering it. A lookup is then a single binary search.
+
+use serde::{Deserialize, Serialize};
+
+pub trait IndexKey: Copy + Ord {
+    const MIN: Self;
+
+    fn checked_next(self) -> Option<Self>;
+}
+
+impl IndexKey for u32 {
+    const MIN: Self = u32::MIN;
+
+    fn checked_next(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+}
+
+impl IndexKey for u128 {
+    const MIN: Self = u128::MIN;
+
+    fn checked_next(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrefixIndex<K, V> {
+    starts: Vec<K>,
+    values: Vec<Option<V>>,
+}
+
+impl<K: IndexKey, V: Copy + PartialEq> PrefixIndex<K, V> {
+    /// Builds the index from inclusive `(start, end, value)` ranges.
+    /// When the same range is present multiple times, the first one wins.
+    pub fn build(mut ranges: Vec<(K, K, V)>) -> Self {
+        // Sort by start, wider ranges first, keeping the insertion order for duplicates
+        ranges.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let mut index = PrefixIndex {
+            starts: vec![K::MIN],
+            values: vec![None],
+        };
+        let mut stack: Vec<(K, K, V)> = Vec::new();
+
+        for (start, end, value) in ranges {
+            index.close(&mut stack, Some(start));
+
+            if let Some(top) = stack.last() {
+                if top.0 == start && top.1 == end {
+                    continue;
+                }
+            }
+
+            index.push(start, Some(value));
+            stack.push((start, end, value));
+        }
+        index.close(&mut stack, None);
+
+        index
+    }
+
+    // Pops every range which ends before `bound` (or all of them), restoring the enclosing value
+    fn close(&mut self, stack: &mut Vec<(K, K, V)>, bound: Option<K>) {
+        while let Some(&(_, end, _)) = stack.last() {
+            if bound.is_some_and(|bound| end >= bound) {
+                break;
+            }
+
+            stack.pop();
+            // Drop partially overlapping ranges which have already ended as well
+            while stack.last().is_some_and(|top| top.1 <= end) {
+                stack.pop();
+            }
+
+            if let Some(next) = end.checked_next() {
+                self.push(next, stack.last().map(|top| top.2));
+            }
+        }
+    }
+
+    fn push(&mut self, start: K, value: Option<V>) {
+        let last = self.starts.len() - 1;
+        if self.starts[last] == start {
+            self.values[last] = value;
+            // Merge with the previous segment if it now carries the same value
+            if last > 0 && self.values[last - 1] == value {
+                self.starts.pop();
+                self.values.pop();
+            }
+        } else if self.values[last] != value {
+            self.starts.push(start);
+            self.values.push(value);
+        }
+    }
+
+    /// Returns the value of the most specific range containing `key`.
+    pub fn lookup(&self, key: K) -> Option<V> {
+        let i = self.starts.partition_point(|start| *start <= key);
+        self.values[i - 1]
+    }
+
+    /// Number of segments with a value, the gaps between the ranges aren't counted.
+    pub fn len(&self) -> usize {
+        self.values.iter().filter(|value| value.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: IndexKey, V> Default for PrefixIndex<K, V> {
+    fn default() -> Self {
+        PrefixIndex {
+            starts: vec![K::MIN],
+            values: vec![None],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefixIndex;
+
+    #[test]
+    fn test_most_specific_match() {
+        let index = PrefixIndex::<u32, u32>::build(vec![
+            (0x01000000, 0x01FFFFFF, 0), // 1.0.0.0/8
+            (0x01010100, 0x010101FF, 1), // 1.1.1.0/24
+            (0x01010000, 0x0101FFFF, 2), // 1.1.0.0/16
+        ]);
+
+        assert_eq!(index.lookup(0x00FFFFFF), None);
+        assert_eq!(index.lookup(0x01000000), Some(0));
+        assert_eq!(index.lookup(0x01010001), Some(2));
+        assert_eq!(index.lookup(0x01010180), Some(1));
+        assert_eq!(index.lookup(0x01010200), Some(2));
+        assert_eq!(index.lookup(0x01020000), Some(0));
+        assert_eq!(index.lookup(0x02000000), None);
+    }
+
+    #[test]
+    fn test_duplicates_and_edges() {
+        let index = PrefixIndex::<u32, u32>::build(vec![
+            (0xF0000000, u32::MAX, 0),
+            (0xF0000000, u32::MAX, 1),
+            (0x00000000, 0x000000FF, 2),
+        ]);
+
+        assert_eq!(index.lookup(0), Some(2));
+        assert_eq!(index.lookup(0x100), None);
+        assert_eq!(index.lookup(u32::MAX), Some(0));
+        assert_eq!(index.len(), 2);
+
+        let empty = PrefixIndex::<u32, u32>::default();
+        assert!(empty.is_empty());
+        assert_eq!(empty.lookup(0x01010101), None);
+    }
+}
@@ -0,0 +1,3 @@
+// The parts of Diglett which are used outside of the binary, e.g. by the benchmarks
+
+pub mod index;
@@ -15,9 +15,16 @@ use tracing::{error, info};
 use uuid::Uuid;
 
 pub mod api;
+pub mod changes;
+pub mod dns;
+pub mod import;
+pub use diglett::index;
 pub mod providers;
+pub mod report;
+pub mod scheduler;
 pub mod settings;
 pub mod utils;
+pub mod whois;
 
 #[macro_use(concat_string)]
 extern crate concat_string;
@@ -28,15 +35,19 @@ extern crate concat_string;
  * 2. Rustls
  * 3. Tracing
  * 4. Settings
- * 5. Load JWT keys
+ * 5. Import and report subcommands
+ * 6. Load JWT keys
  * == POKEDEX ==
- * 6. Login to Pokedex
+ * 7. Login to Pokedex
  * == TOKIO ==
- * 7. Tokio setup
- * 8. Graceful shutdown task
+ * 8. Tokio setup
+ * 9. Graceful shutdown task
  * == RUNTIME ==
- * 9. Load providers
- * 10. Axum API task
+ * 10. Load providers
+ * 11. Scheduler task
+ * 12. Whois task
+ * 13. DNS task
+ * 14. Axum API task
  */
 #[tokio::main]
 async fn main() {
@@ -57,6 +68,22 @@ async fn main() {
     let (config, settings) = mtilib::settings::deserialize_from_config("config.toml");
     let settings: Arc<Settings> = Arc::new(settings);
 
+    // Import subcommand (diglett import <tarball>)
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(|arg| arg.as_str()) == Some("import") {
+        match args.get(2) {
+            Some(tarball) => import::run(&config, tarball).await,
+            None => error!("Usage: diglett import <tarball>"),
+        }
+        return;
+    }
+
+    // Report subcommand (diglett report [kind])
+    if args.get(1).map(|arg| arg.as_str()) == Some("report") {
+        report::run(&config, args.get(2).map(|arg| arg.as_str())).await;
+        return;
+    }
+
     // Load JWT keys if api.auth is set to true
     let mut jwt_keys = None;
     if settings.api.auth {
@@ -140,18 +167,52 @@ async fn main() {
     let providers = Arc::new(RwLock::new(Providers::load(&config).await));
 
     // Scheduler task
-    // let scheduler_providers = providers.clone();
-    // let scheduler_token = task_token.clone();
-    // task_tracker.spawn(async move {
-    //     tokio::select! {
-    //         _ = scheduler::run(scheduler_providers) => {
-    //             info!("Scheduler task exited on its own!")
-    //         }
-    //         () = scheduler_token.cancelled() => {
-    //             info!("Scheduler task cancelled succesfully!");
-    //         }
-    //     }
-    // });
+    let scheduler_providers = providers.clone();
+    let scheduler_token = task_token.clone();
+    task_tracker.spawn(async move {
+        tokio::select! {
+            _ = scheduler::run(scheduler_providers) => {
+                info!("Scheduler task exited on its own!")
+            }
+            () = scheduler_token.cancelled() => {
+                info!("Scheduler task cancelled succesfully!");
+            }
+        }
+    });
+
+    // Whois, only when the whois section is configured
+    if settings.whois.is_some() {
+        let whois_settings = settings.clone();
+        let whois_providers = providers.clone();
+        let whois_token = task_token.clone();
+        task_tracker.spawn(async move {
+            tokio::select! {
+                () = whois::run(whois_settings, whois_providers) => {
+                    info!("Whois task exited on its own!");
+                }
+                () = whois_token.cancelled() => {
+                    info!("Whois task cancelled succesfully!");
+                }
+            }
+        });
+    }
+
+    // DNS, only when the dns section is configured
+    if settings.dns.is_some() {
+        let dns_settings = settings.clone();
+        let dns_providers = providers.clone();
+        let dns_token = task_token.clone();
+        task_tracker.spawn(async move {
+            tokio::select! {
+                () = dns::run(dns_settings, dns_providers) => {
+                    info!("DNS task exited on its own!");
+                }
+                () = dns_token.cancelled() => {
+                    info!("DNS task cancelled succesfully!");
+                }
+            }
+        });
+    }
 
     // Axum API
     task_tracker.spawn(async move {
@@ -1,154 +1,1431 @@
-use std::{path::Path, time::SystemTime};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    mem,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
+use archive::SourceArchive;
 use config::Config;
-use reqwest::header::USER_AGENT;
+use download::{DownloadError, DownloadOutcome, SourceVerification};
+use mtilib::types::{
+    AddressResponse, AllocationState, AsnNameResponse, AsnResponse, GeoResponse, HolderResponse,
+    PrefixesResponse, Rir, RoutedResponse, RpkiResponse, RpkiState, SpecialPurposeResponse,
+};
+use serde::{Deserialize, Serialize};
+use snapshot::{ProviderSnapshot, Snapshot, SnapshotError, SourceFingerprint};
 use stats::StatsProvider;
-use tokio::{
-    fs::{self, File},
-    io,
+use tokio::{fs, sync::RwLock, task::JoinError};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    index::PrefixIndex,
+    utils::{self, CIDR},
 };
-use tracing::{debug, error};
 
+pub mod archive;
+pub mod bogons;
+pub mod download;
+pub mod geoip;
 pub mod iana;
+pub mod mrt;
+pub mod rpki;
+pub mod snapshot;
 pub mod stats;
 pub mod thyme;
 
+#[derive(Clone, Debug)]
 pub struct ProviderSource {
     pub filepath: String,
-    pub url: String,
+    /// Where the file gets downloaded from, either http(s):// or file://
+    pub url: Option<String>,
     pub max_time: u32,
+    pub verify: Option<SourceVerification>,
+    /// Stale files are loaded as they are instead of being downloaded
+    pub offline: bool,
+    /// Where the versions of the file are kept, so that they can be compared with the current one
+    pub archive: Option<SourceArchive>,
 }
 
 impl ProviderSource {
-    pub async fn download(&self) {
-        let filepath = Path::new(&self.filepath);
-
-        if fs::metadata(filepath).await.is_err() {
-            let prefix = filepath.parent().unwrap();
-            fs::create_dir_all(prefix).await.unwrap();
-        }
-
-        let client = reqwest::Client::new();
-
-        let file_in = client
-			.get(&self.url)
-			.header(USER_AGENT, "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/14.0.3 Safari/605.1.15")
-			.send()
-			.await
-			.unwrap_or_else(|_| panic!("Failed to download file {}!", filepath.to_str().unwrap()))
-			.bytes()
-			.await
-			.unwrap();
-        let mut file_in_ref = file_in.as_ref();
-        let mut file_out = File::create(filepath)
-            .await
-            .unwrap_or_else(|_| panic!("Failed to create file {}!", filepath.to_str().unwrap()));
+    pub fn can_download(&self) -> bool {
+        !self.offline && self.url.is_some()
+    }
 
-        io::copy(&mut file_in_ref, &mut file_out)
-            .await
-            .unwrap_or_else(|_| panic!("Failed to write file {}!", filepath.to_str().unwrap()));
+    pub async fn download(&self) -> Result<DownloadOutcome, DownloadError> {
+        download::download(self).await
     }
 
+    /// Whether the file is there and fresh enough to be used without downloading it again.
+    ///
+    /// The file is as fresh as its last download or check (whichever is later), a file whose age
+    /// can't be told (unreadable metadata, timestamps in the future) counts as stale.
     pub async fn check(&self) -> bool {
-        // Check if we need to download a fresh asn prefixes file
-        let filepath = Path::new(&self.filepath);
-
-        if !filepath.exists() {
-            debug!("File {} missing!", filepath.to_str().unwrap());
-            false
-        } else {
-            let file_metadata = fs::metadata(filepath).await.unwrap();
-
-            if let Ok(time_modified) = file_metadata.modified() {
-                let time_now = SystemTime::now();
-                if time_now.duration_since(time_modified).unwrap().as_secs() > self.max_time as u64
-                {
-                    debug!("File {} stale!", filepath.to_str().unwrap());
-                    false
-                } else {
-                    debug!("File {} OK!", filepath.to_str().unwrap());
-                    true
-                }
-            } else {
+        let time_modified = match fs::metadata(&self.filepath)
+            .await
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(time_modified) => time_modified,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                debug!("File {} missing!", self.filepath);
+                return false;
+            }
+            Err(error) => {
                 error!(
-                    "Couldn't read modified timestamp of file {}!",
-                    filepath.to_str().unwrap()
+                    "Couldn't read modified timestamp of file {}! ({})",
+                    self.filepath, error
+                );
+                return false;
+            }
+        };
+
+        let time_checked = download::last_checked(self)
+            .await
+            .map_or(time_modified, |time_checked| {
+                time_checked.max(time_modified)
+            });
+
+        match SystemTime::now().duration_since(time_checked) {
+            Ok(age) if age.as_secs() <= self.max_time as u64 => {
+                debug!("File {} OK!", self.filepath);
+                true
+            }
+            Ok(_) => {
+                debug!("File {} stale!", self.filepath);
+                false
+            }
+            Err(_) => {
+                warn!(
+                    "File {} was checked in the future, treating it as stale!",
+                    self.filepath
                 );
                 false
             }
         }
     }
+
+    /// Offline source of the file, which is never downloaded.
+    #[cfg(test)]
+    pub fn local(filepath: impl Into<String>) -> Self {
+        ProviderSource {
+            filepath: filepath.into(),
+            url: None,
+            max_time: 0,
+            verify: None,
+            offline: true,
+            archive: None,
+        }
+    }
+
+    /// Offline source of the file in `tests/fixtures`.
+    #[cfg(test)]
+    pub fn fixture(name: &str) -> Self {
+        ProviderSource::local(format!(
+            "{}/tests/fixtures/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+    }
 }
 
 pub trait CheckAndDownloadSource {
-    fn check_and_download(&self) -> impl std::future::Future<Output = ()> + Send;
+    fn check_and_download(
+        &self,
+    ) -> impl std::future::Future<Output = Result<(), DownloadError>> + Send;
 }
 
 impl CheckAndDownloadSource for ProviderSource {
-    async fn check_and_download(&self) {
-        if !self.check().await {
-            self.download().await;
+    async fn check_and_download(&self) -> Result<(), DownloadError> {
+        if self.check().await {
+            return Ok(());
+        }
+
+        if !self.can_download() {
+            return match Path::new(&self.filepath).exists() {
+                true => {
+                    warn!(
+                        "File {} is stale, loading it anyway (offline)!",
+                        self.filepath
+                    );
+                    Ok(())
+                }
+                false => {
+                    error!("File {} is missing and can't be downloaded!", self.filepath);
+                    Err(DownloadError::Offline)
+                }
+            };
         }
+
+        // Keep using the last good file, if there is one
+        self.download()
+            .await
+            .map(|_| ())
+            .inspect_err(|error| error!("Failed to download file {}! ({})", self.filepath, error))
     }
 }
 
-impl CheckAndDownloadSource for Vec<ProviderSource> {
-    async fn check_and_download(&self) {
-        for source in self {
-            source.check_and_download().await;
+/// The kinds of address lookups answered by the providers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LookupKind {
+    AllocationState,
+    TopRir,
+    Rir,
+    Asn,
+    Country,
+    RegistrationDate,
+    OpaqueId,
+    Routed,
+    Geolocation,
+    SpecialPurpose,
+}
+
+impl LookupKind {
+    pub const ALL: [LookupKind; 10] = [
+        LookupKind::AllocationState,
+        LookupKind::TopRir,
+        LookupKind::Rir,
+        LookupKind::Asn,
+        LookupKind::Country,
+        LookupKind::RegistrationDate,
+        LookupKind::OpaqueId,
+        LookupKind::Routed,
+        LookupKind::Geolocation,
+        LookupKind::SpecialPurpose,
+    ];
+
+    pub fn id(&self) -> &str {
+        match self {
+            LookupKind::AllocationState => "allocation_state",
+            LookupKind::TopRir => "top_rir",
+            LookupKind::Rir => "rir",
+            LookupKind::Asn => "asn",
+            LookupKind::Country => "country",
+            LookupKind::RegistrationDate => "registration_date",
+            LookupKind::OpaqueId => "opaque_id",
+            LookupKind::Routed => "routed",
+            LookupKind::Geolocation => "geolocation",
+            LookupKind::SpecialPurpose => "special_purpose",
         }
     }
+
+    // The order in which the providers are asked when the config doesn't say otherwise
+    fn default_precedence(&self) -> &[&str] {
+        match self {
+            LookupKind::AllocationState => &["iana.reserved", "arin.stats", "bogons.full"],
+            LookupKind::TopRir => &["thyme.rir_allocations"],
+            LookupKind::Rir => &["iana.recovered", "arin.stats"],
+            LookupKind::Asn => &["mrt.rib", "thyme.asn_prefixes"],
+            LookupKind::Country => &["arin.stats"],
+            LookupKind::RegistrationDate => &["arin.stats"],
+            LookupKind::OpaqueId => &["arin.stats"],
+            LookupKind::Routed => &["mrt.rib", "thyme.asn_prefixes"],
+            LookupKind::Geolocation => &["geoip.mmdb"],
+            LookupKind::SpecialPurpose => &["iana.reserved"],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LookupValue {
+    AllocationState(AllocationState),
+    Rir(Rir),
+    Asn(u32),
+    Country(String),
+    RegistrationDate(String),
+    OpaqueId(String),
+    /// The most specific routed prefix and its origins, the most seen first
+    Route {
+        prefix: String,
+        origins: Vec<u32>,
+    },
+    Geolocation(GeoResponse),
+    SpecialPurpose(SpecialPurposeResponse),
+}
+
+/// The reverse lookups answered by the providers, all of the prefixes matching the filter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrefixFilter {
+    Asn(u32),
+    Country(String),
+    Rir(Rir),
 }
 
-// #[derive(Clone)]
-// pub enum Provider<'a> {
-//     StatsProvider(&'a StatsProvider),
-//     IanaReservedProvider(&'a iana::reserved::ReservedProvider),
-//     IanaRecoveredProvider(&'a iana::recovered::RecoveredProvider),
-//     ThymeAsnPrefixesProvider(&'a thyme::asn_prefixes::AsnPrefixesProvider),
-//     ThymeRirAllocationsProvider(&'a thyme::rir_allocations::RirAllocationsProvider),
-// }
+impl PrefixFilter {
+    // The providers are asked in the same order as for the matching address lookup
+    fn kind(&self) -> LookupKind {
+        match self {
+            PrefixFilter::Asn(_) => LookupKind::Asn,
+            PrefixFilter::Country(_) => LookupKind::Country,
+            PrefixFilter::Rir(_) => LookupKind::TopRir,
+        }
+    }
+}
 
-// impl<'a> Provider<'a> {
-//     pub fn sources(&self) -> &Vec<ProviderSource> {
-//         match self {
-//             Provider::StatsProvider(stats_provider) => &stats_provider.sources,
-//             Provider::IanaReservedProvider(reserved_provider) => &reserved_provider.sources,
-//             Provider::IanaRecoveredProvider(recovered_provider) => &recovered_provider.sources,
-//             Provider::ThymeAsnPrefixesProvider(asn_prefixes_provider) => {
-//                 &asn_prefixes_provider.sources
-//             }
-//             Provider::ThymeRirAllocationsProvider(rir_allocations_provider) => {
-//                 &rir_allocations_provider.sources
-//             }
-//         }
-//     }
-// }
+#[derive(Clone, Debug, Default)]
+pub struct ProviderStats {
+    pub entries: usize,
+}
 
+/// How the last (re)load of a provider and the downloads of its sources went.
+#[derive(Clone, Debug, Default)]
+pub struct ProviderStatus {
+    pub loaded_at: Option<SystemTime>,
+    pub load_duration: Option<Duration>,
+    pub last_error: Option<String>,
+    /// Last download error of each source by its file path, cleared by a successful download
+    pub source_errors: HashMap<String, String>,
+    /// Whether the provider was restored from the snapshot instead of being parsed from its sources
+    pub from_snapshot: bool,
+}
+
+/// Why a provider couldn't be (re)loaded, the malformed lines are skipped instead.
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        filepath: String,
+        error: std::io::Error,
+    },
+    /// The source file as a whole is malformed, not just some of its lines
+    Format { filepath: String, error: String },
+    /// The load task panicked or was cancelled
+    Task(JoinError),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io { filepath, error } => {
+                write!(f, "failed to read {}: {}", filepath, error)
+            }
+            LoadError::Format { filepath, error } => write!(f, "malformed {}: {}", filepath, error),
+            LoadError::Task(error) => write!(f, "load task failed: {}", error),
+        }
+    }
+}
+
+/// A malformed line of a source, the lines are numbered from 1. For the binary sources the line
+/// is the byte offset of the malformed record instead.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedLine {
+    pub filepath: String,
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Malformed lines skipped while loading the sources, all of them are counted but only the first few are kept.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SkippedLines {
+    pub count: usize,
+    pub lines: Vec<SkippedLine>,
+}
+
+impl SkippedLines {
+    const MAX_KEPT: usize = 100;
+
+    pub fn skip(&mut self, source: &ProviderSource, line: usize, reason: impl Display) {
+        self.count += 1;
+        if self.lines.len() < SkippedLines::MAX_KEPT {
+            warn!(
+                "Skipping malformed line {} of {}! ({})",
+                line, source.filepath, reason
+            );
+            self.lines.push(SkippedLine {
+                filepath: source.filepath.clone(),
+                line,
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    /// Same as `skip` for a record of a binary source, at the byte offset.
+    pub fn skip_record(&mut self, source: &ProviderSource, offset: usize, reason: impl Display) {
+        self.count += 1;
+        if self.lines.len() < SkippedLines::MAX_KEPT {
+            warn!(
+                "Skipping malformed record at byte {} of {}! ({})",
+                offset, source.filepath, reason
+            );
+            self.lines.push(SkippedLine {
+                filepath: source.filepath.clone(),
+                line: offset,
+                reason: reason.to_string(),
+            });
+        }
+    }
+}
+
+pub trait Provider: Send + Sync {
+    /// Parses the provider from its (already downloaded) sources, skipping the malformed lines.
+    fn load_sources(
+        sources: Vec<ProviderSource>,
+    ) -> impl Future<Output = Result<Self, LoadError>> + Send
+    where
+        Self: Sized;
+
+    fn sources(&self) -> &Vec<ProviderSource>;
+
+    /// Parsed data written to the snapshot, `None` for the providers which are always parsed.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Rebuilds the provider from its snapshot data, `None` when the data doesn't match the provider.
+    fn restore(_data: &[u8], _sources: Vec<ProviderSource>) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Malformed lines (or records) skipped while loading, `None` for the providers which can't skip any.
+    fn skipped(&self) -> Option<&SkippedLines> {
+        None
+    }
+
+    /// Answers a single kind of lookup, `None` when the kind isn't supported or the address isn't known.
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue>;
+
+    /// Registry details of an AS number.
+    fn autsys(&self, _asn: u32) -> Option<AsnResponse> {
+        None
+    }
+
+    /// Name of the organisation using the AS number.
+    fn autsys_name(&self, _asn: u32) -> Option<AsnNameResponse> {
+        None
+    }
+
+    /// All of the address blocks and ASNs registered to the holder with the opaque id.
+    fn holder(&self, _opaque_id: &str) -> Option<HolderResponse> {
+        None
+    }
+
+    /// All of the prefixes matching the filter, `None` when the filter isn't supported.
+    fn prefixes(&self, _filter: &PrefixFilter) -> Option<Vec<CIDR>> {
+        None
+    }
+
+    /// Inclusive address ranges the provider has data for, `None` when it can't list them.
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        None
+    }
+
+    /// Route origin validation of the route, `None` when the provider doesn't have any ROAs.
+    fn validate(&self, _prefix: &CIDR, _origin: Option<u32>) -> Option<RpkiState> {
+        None
+    }
+
+    fn stats(&self) -> ProviderStats;
+}
+
+type LoadResult = Result<Box<dyn Provider>, LoadError>;
+type LoadFn = fn(Vec<ProviderSource>) -> Pin<Box<dyn Future<Output = LoadResult> + Send>>;
+type RestoreFn = fn(&[u8], Vec<ProviderSource>) -> Option<Box<dyn Provider>>;
+
+fn loader<P: Provider + 'static>(
+    sources: Vec<ProviderSource>,
+) -> Pin<Box<dyn Future<Output = LoadResult> + Send>> {
+    Box::pin(async move {
+        P::load_sources(sources)
+            .await
+            .map(|provider| Box::new(provider) as Box<dyn Provider>)
+    })
+}
+
+fn restorer<P: Provider + 'static>(
+    data: &[u8],
+    sources: Vec<ProviderSource>,
+) -> Option<Box<dyn Provider>> {
+    P::restore(data, sources).map(|provider| Box::new(provider) as Box<dyn Provider>)
+}
+
+// Stands in for a provider whose first load failed, until one of its reloads succeeds
+struct UnloadedProvider {
+    sources: Vec<ProviderSource>,
+}
+
+impl Provider for UnloadedProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        Ok(UnloadedProvider { sources })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn lookup(&self, _kind: LookupKind, _address: IpAddr) -> Option<LookupValue> {
+        None
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats::default()
+    }
+}
+
+fn log_skipped(id: &str, provider: &dyn Provider) {
+    if let Some(skipped) = provider.skipped().filter(|skipped| skipped.count > 0) {
+        warn!("Provider {} skipped {} malformed lines!", id, skipped.count);
+    }
+}
+
+// The parsing is mostly CPU bound, it runs on a blocking thread so that it doesn't hold up the
+// runtime's workers, which keep serving the lookups in the meantime
+async fn parse(load: LoadFn, sources: Vec<ProviderSource>) -> LoadResult {
+    let handle = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || handle.block_on(load(sources)))
+        .await
+        .map_err(LoadError::Task)
+        .and_then(|result| result)
+}
+
+/// Provider implementations which can be used in the config, a provider's type defaults to its id.
+pub const REGISTRY: [(&str, LoadFn, RestoreFn); 10] = [
+    (
+        "arin.stats",
+        loader::<StatsProvider>,
+        restorer::<StatsProvider>,
+    ),
+    (
+        "iana.reserved",
+        loader::<iana::reserved::ReservedProvider>,
+        restorer::<iana::reserved::ReservedProvider>,
+    ),
+    (
+        "iana.recovered",
+        loader::<iana::recovered::RecoveredProvider>,
+        restorer::<iana::recovered::RecoveredProvider>,
+    ),
+    (
+        "thyme.asn_prefixes",
+        loader::<thyme::asn_prefixes::AsnPrefixesProvider>,
+        restorer::<thyme::asn_prefixes::AsnPrefixesProvider>,
+    ),
+    (
+        "thyme.rir_allocations",
+        loader::<thyme::rir_allocations::RirAllocationsProvider>,
+        restorer::<thyme::rir_allocations::RirAllocationsProvider>,
+    ),
+    (
+        "thyme.as_names",
+        loader::<thyme::as_names::AsNamesProvider>,
+        restorer::<thyme::as_names::AsNamesProvider>,
+    ),
+    (
+        "mrt.rib",
+        loader::<mrt::RibProvider>,
+        restorer::<mrt::RibProvider>,
+    ),
+    (
+        "rpki.roas",
+        loader::<rpki::RpkiProvider>,
+        restorer::<rpki::RpkiProvider>,
+    ),
+    (
+        "geoip.mmdb",
+        loader::<geoip::GeoIpProvider>,
+        restorer::<geoip::GeoIpProvider>,
+    ),
+    (
+        "bogons.full",
+        loader::<bogons::BogonsProvider>,
+        restorer::<bogons::BogonsProvider>,
+    ),
+];
+
+#[derive(Clone)]
+struct ProviderEntry {
+    id: String,
+    /// The registry type of the provider
+    kind: String,
+    load: LoadFn,
+    /// Shared with the clones of the providers, which are walked without holding the lock
+    provider: Arc<dyn Provider>,
+    /// What the sources looked like before they were parsed, `None` when any of them was missing
+    fingerprints: Option<Vec<SourceFingerprint>>,
+    status: ProviderStatus,
+}
+
+/// A provider parsed from the archived versions of its sources.
+pub struct ArchivedProvider {
+    pub id: String,
+    /// Day (since the unix epoch) of the oldest of the archived sources
+    pub day: i64,
+    pub provider: Box<dyn Provider>,
+}
+
+/// The providers parsed from the versions of their sources which were in use on a day.
+pub struct ArchivedProviders {
+    /// Day (since the unix epoch) the versions were asked for
+    pub day: i64,
+    /// Generation of the providers the archive was looked up for
+    generation: u64,
+    pub providers: Vec<ArchivedProvider>,
+    /// Ids of the providers without all of their sources archived from the day (or failing to load)
+    pub missing: Vec<String>,
+}
+
+// How many of the parsed days are kept, each of them holds a full copy of the archived providers
+const ARCHIVE_CACHE_DAYS: usize = 4;
+
+/// The loaded providers, a clone is cheap since it shares the parsed data.
+#[derive(Clone)]
 pub struct Providers {
-    pub stats: StatsProvider,
-    pub iana: iana::Providers,
-    pub thyme: thyme::Providers,
+    entries: Vec<ProviderEntry>,
+    precedence: HashMap<LookupKind, Vec<String>>,
+    /// Where the parsed providers are saved, so that the next start doesn't have to parse them again
+    snapshot: Option<PathBuf>,
+    /// Bumped on every reload, which may also have archived a new version of the sources
+    generation: u64,
+    /// The last parsed days of the archive, the most recently used first
+    archived: Arc<tokio::sync::Mutex<Vec<Arc<ArchivedProviders>>>>,
 }
 
 impl Providers {
     pub async fn load(config: &Config) -> Self {
+        let mut providers = Providers {
+            entries: Vec::new(),
+            precedence: load_precedence(config),
+            snapshot: config
+                .get_string("providers.snapshot")
+                .ok()
+                .map(PathBuf::from),
+            generation: 0,
+            archived: Arc::default(),
+        };
+
+        // The snapshot stays mapped only while the providers are being restored from it
+        let snapshot = providers.snapshot.as_deref().and_then(open_snapshot);
+        let snapshot_providers = snapshot
+            .as_ref()
+            .and_then(|snapshot| {
+                snapshot
+                    .providers()
+                    .inspect_err(|error| warn!("Ignoring the provider snapshot! ({})", error))
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        let mut parsed = false;
+        for id in configured_providers(config) {
+            let kind = config
+                .get_string(&concat_string!("providers.", id, ".type"))
+                .unwrap_or(id.clone());
+            let (load, restore) = match REGISTRY
+                .iter()
+                .find(|(registry_id, _, _)| *registry_id == kind)
+            {
+                Some((_, load, restore)) => (*load, *restore),
+                None => panic!("Invalid config (unknown type {} of provider {})!", kind, id),
+            };
+
+            // Check if we need to redownload the files
+            let sources = load_provider_sources(config, &id).unwrap();
+            let mut status = ProviderStatus::default();
+            for source in sources.iter() {
+                if let Err(error) = source.check_and_download().await {
+                    status
+                        .source_errors
+                        .insert(source.filepath.clone(), error.to_string());
+                }
+            }
+
+            // Taken before parsing, a source replaced in the meantime only makes the next start parse it again
+            let fingerprints = snapshot::fingerprints(&sources);
+            let started = Instant::now();
+            let restored = snapshot_providers
+                .iter()
+                .find(|entry| {
+                    entry.id == id
+                        && entry.kind == kind
+                        && fingerprints.as_ref() == Some(&entry.sources)
+                })
+                .and_then(|entry| restore(entry.data, sources.clone()));
+
+            // A provider which fails to load stays empty, the scheduler reloads it after the next download
+            let provider = match restored {
+                Some(provider) => {
+                    info!("Restored provider {} from the snapshot!", id);
+                    status.loaded_at = Some(SystemTime::now());
+                    status.load_duration = Some(started.elapsed());
+                    status.from_snapshot = true;
+                    provider
+                }
+                None => match parse(load, sources.clone()).await {
+                    Ok(provider) => {
+                        status.loaded_at = Some(SystemTime::now());
+                        status.load_duration = Some(started.elapsed());
+                        log_skipped(&id, provider.as_ref());
+                        parsed = true;
+                        provider
+                    }
+                    Err(error) => {
+                        error!("Failed to load provider {}! ({})", id, error);
+                        status.last_error = Some(error.to_string());
+                        Box::new(UnloadedProvider { sources })
+                    }
+                },
+            };
+
+            providers.entries.push(ProviderEntry {
+                provider: Arc::from(provider),
+                id,
+                kind,
+                load,
+                fingerprints,
+                status,
+            });
+        }
+        drop(snapshot_providers);
+        drop(snapshot);
+
+        if parsed {
+            providers.save_snapshot();
+        }
+
+        // The default precedence lists all of the known providers, only the configured ones matter
+        for (kind, ids) in providers.precedence.iter() {
+            if config
+                .get_array(&concat_string!("lookup.", kind.id()))
+                .is_err()
+            {
+                continue;
+            }
+
+            for id in ids {
+                if providers.get(id).is_none() {
+                    warn!(
+                        "Provider {} used for {} lookups is not configured!",
+                        id,
+                        kind.id()
+                    );
+                }
+            }
+        }
+
+        providers
+    }
+
+    #[cfg(test)]
+    pub fn empty() -> Self {
         Providers {
-            stats: StatsProvider::load(config).await,
-            iana: iana::Providers {
-                reserved: iana::reserved::ReservedProvider::load(config).await,
-                recovered: iana::recovered::RecoveredProvider::load(config).await,
+            entries: Vec::new(),
+            precedence: LookupKind::ALL
+                .iter()
+                .map(|kind| {
+                    (
+                        *kind,
+                        kind.default_precedence()
+                            .iter()
+                            .map(|id| id.to_string())
+                            .collect(),
+                    )
+                })
+                .collect(),
+            snapshot: None,
+            generation: 0,
+            archived: Arc::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn insert<P: Provider + 'static>(&mut self, id: &str, provider: P) {
+        self.entries.push(ProviderEntry {
+            id: id.to_string(),
+            kind: id.to_string(),
+            load: loader::<P>,
+            provider: Arc::new(provider),
+            fingerprints: None,
+            status: ProviderStatus::default(),
+        });
+    }
+
+    /// A few hand made entries shared by the tests of the API and the servers, 1.0.0.0/8 with
+    /// AS13335 and its 1.1.1.0/24 route, 2001:db8::/32 routed by AS64496 and the ROAs of both.
+    #[cfg(test)]
+    pub async fn fixture() -> Self {
+        use std::str::FromStr;
+
+        use mtilib::types::{AllocationState, Rir};
+
+        use crate::{index::PrefixIndex, utils::CIDR};
+        use rpki::{Roa, RpkiProvider};
+        use stats::{AsnEntry, StatsEntry, StatsProvider};
+        use thyme::asn_prefixes::{AsnPrefixEntry, AsnPrefixesProvider};
+
+        let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+        stats.values.push(StatsEntry {
+            cidr: CIDR::from_str("1.0.0.0/8").unwrap(),
+            allocation_state: AllocationState::Allocated,
+            rir: Rir::Apnic,
+            country: Some(String::from("AU")),
+            registration_date: Some(String::from("2011-08-11")),
+            opaque_id: None,
+        });
+        stats.index = CidrIndex::build(stats.values.iter().map(|x| &x.cidr));
+        stats.asns.push(AsnEntry {
+            start: 13335,
+            count: 1,
+            allocation_state: AllocationState::Allocated,
+            rir: Rir::Arin,
+            country: Some(String::from("US")),
+            registration_date: Some(String::from("2010-07-14")),
+            opaque_id: None,
+        });
+        stats.asn_index = PrefixIndex::build(vec![(13335, 13335, 0)]);
+
+        let mut asn_prefixes = AsnPrefixesProvider::load_sources(vec![]).await.unwrap();
+        asn_prefixes.values.push(AsnPrefixEntry {
+            cidr: CIDR::from_str("1.1.1.0/24").unwrap(),
+            asn: 13335,
+        });
+        asn_prefixes.values.push(AsnPrefixEntry {
+            cidr: CIDR::from_str("2001:db8::/32").unwrap(),
+            asn: 64496,
+        });
+        asn_prefixes.index = CidrIndex::build(asn_prefixes.values.iter().map(|x| &x.cidr));
+
+        let mut rpki = RpkiProvider::load_sources(vec![]).await.unwrap();
+        rpki.values.push(Roa {
+            cidr: CIDR::from_str("1.1.0.0/16").unwrap(),
+            max_length: 24,
+            asn: 13335,
+        });
+        rpki.values.push(Roa {
+            cidr: CIDR::from_str("2001:db8::/32").unwrap(),
+            max_length: 32,
+            asn: 64499,
+        });
+        rpki.index = RpkiProvider::build_index(&rpki.values);
+        rpki.sources.push(ProviderSource {
+            max_time: u32::MAX,
+            ..ProviderSource::fixture("roas.json")
+        });
+
+        let mut providers = Providers::empty();
+        providers.insert("arin.stats", stats);
+        providers.insert("thyme.asn_prefixes", asn_prefixes);
+        providers.insert("rpki.roas", rpki);
+        providers
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.id.clone()).collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&dyn Provider> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.provider.as_ref())
+    }
+
+    /// The providers of the registry type, in the order of their ids.
+    pub fn of_type<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a dyn Provider> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.kind == kind)
+            .map(|entry| entry.provider.as_ref())
+    }
+
+    /// The address ranges of all of the providers, in no particular order.
+    pub fn ranges(&self) -> Vec<(IpAddr, IpAddr)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.provider.ranges())
+            .flatten()
+            .collect()
+    }
+
+    /// Asks the providers in the configured order, the first answer wins.
+    pub fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        self.precedence
+            .get(&kind)?
+            .iter()
+            .filter_map(|id| self.get(id))
+            .find_map(|provider| provider.lookup(kind, address))
+    }
+
+    pub fn allocation_state(&self, address: IpAddr) -> AllocationState {
+        match self.lookup(LookupKind::AllocationState, address) {
+            Some(LookupValue::AllocationState(state)) => state,
+            _ => AllocationState::Unknown,
+        }
+    }
+
+    pub fn top_rir(&self, address: IpAddr) -> Option<Rir> {
+        match self.lookup(LookupKind::TopRir, address) {
+            Some(LookupValue::Rir(rir)) => Some(rir),
+            _ => None,
+        }
+    }
+
+    pub fn rir(&self, address: IpAddr) -> Option<Rir> {
+        match self.lookup(LookupKind::Rir, address) {
+            Some(LookupValue::Rir(rir)) => Some(rir),
+            _ => None,
+        }
+    }
+
+    pub fn asn(&self, address: IpAddr) -> Option<u32> {
+        match self.lookup(LookupKind::Asn, address) {
+            Some(LookupValue::Asn(asn)) => Some(asn),
+            _ => None,
+        }
+    }
+
+    pub fn country(&self, address: IpAddr) -> Option<String> {
+        match self.lookup(LookupKind::Country, address) {
+            Some(LookupValue::Country(country)) => Some(country),
+            _ => None,
+        }
+    }
+
+    pub fn address(&self, address: IpAddr) -> AddressResponse {
+        let asn = self.asn(address);
+
+        AddressResponse {
+            allocation_state: self.allocation_state(address),
+            top_rir: self.top_rir(address),
+            rir: self.rir(address),
+            asn,
+            asn_name: asn.and_then(|asn| self.autsys_name(asn)),
+            country: self.country(address),
+            registration_date: match self.lookup(LookupKind::RegistrationDate, address) {
+                Some(LookupValue::RegistrationDate(date)) => Some(date),
+                _ => None,
+            },
+            opaque_id: match self.lookup(LookupKind::OpaqueId, address) {
+                Some(LookupValue::OpaqueId(opaque_id)) => Some(opaque_id),
+                _ => None,
+            },
+            rpki: self.rpki(address).map(|rpki| rpki.state),
+            geo: self.geo(address),
+            special_purpose: self.special_purpose(address),
+        }
+    }
+
+    pub fn special_purpose(&self, address: IpAddr) -> Option<SpecialPurposeResponse> {
+        match self.lookup(LookupKind::SpecialPurpose, address) {
+            Some(LookupValue::SpecialPurpose(special_purpose)) => Some(special_purpose),
+            _ => None,
+        }
+    }
+
+    pub fn geo(&self, address: IpAddr) -> Option<GeoResponse> {
+        match self.lookup(LookupKind::Geolocation, address) {
+            Some(LookupValue::Geolocation(geo)) => Some(geo),
+            _ => None,
+        }
+    }
+
+    pub fn routed(&self, address: IpAddr) -> RoutedResponse {
+        match self.lookup(LookupKind::Routed, address) {
+            Some(LookupValue::Route { prefix, origins }) => RoutedResponse {
+                routed: true,
+                moas: origins.len() > 1,
+                prefix: Some(prefix),
+                origins,
+            },
+            _ => RoutedResponse {
+                routed: false,
+                prefix: None,
+                origins: Vec::new(),
+                moas: false,
             },
-            thyme: thyme::Providers {
-                asn_prefixes: thyme::asn_prefixes::AsnPrefixesProvider::load(config).await,
-                rir_allocations: thyme::rir_allocations::RirAllocationsProvider::load(config)
-                    .await,
+        }
+    }
+
+    /// Validates the route of the address with its (most seen) origin, `None` when no ROAs are loaded.
+    pub fn rpki(&self, address: IpAddr) -> Option<RpkiResponse> {
+        let route = match self.lookup(LookupKind::Routed, address) {
+            Some(LookupValue::Route { prefix, origins }) => CIDR::from_str(&prefix)
+                .ok()
+                .map(|prefix| (prefix, origins.first().copied())),
+            _ => None,
+        };
+
+        let validate = |prefix: &CIDR, origin| {
+            self.entries
+                .iter()
+                .find_map(|entry| entry.provider.validate(prefix, origin))
+        };
+        let state = match route {
+            Some((prefix, origin)) => validate(&prefix, origin)?,
+            // Unrouted addresses don't have a route to validate, only check that ROAs are loaded
+            None => validate(&CIDR::new(address, 0), None).map(|_| RpkiState::NotFound)?,
+        };
+
+        Some(RpkiResponse {
+            state,
+            prefix: route.map(|(prefix, _)| prefix.to_string()),
+            asn: route.and_then(|(_, origin)| origin),
+        })
+    }
+
+    /// Reverse lookup from the first provider supporting the filter, `None` when there is no such provider.
+    pub fn prefixes(&self, filter: &PrefixFilter, aggregate: bool) -> Option<PrefixesResponse> {
+        let mut prefixes = self
+            .precedence
+            .get(&filter.kind())?
+            .iter()
+            .filter_map(|id| self.get(id))
+            .find_map(|provider| provider.prefixes(filter))?;
+
+        let (ipv4_addresses, ipv6_addresses) = utils::address_count(&prefixes);
+        prefixes = match aggregate {
+            true => utils::aggregate(&prefixes),
+            false => {
+                prefixes.sort_by_key(|cidr| (cidr.first_address(), cidr.mask));
+                prefixes.dedup();
+                prefixes
+            }
+        };
+
+        Some(PrefixesResponse {
+            prefixes: prefixes.iter().map(|cidr| cidr.to_string()).collect(),
+            ipv4_addresses,
+            ipv6_addresses,
+        })
+    }
+
+    pub fn autsys(&self, asn: u32) -> Option<AsnResponse> {
+        self.entries
+            .iter()
+            .find_map(|entry| entry.provider.autsys(asn))
+    }
+
+    pub fn autsys_name(&self, asn: u32) -> Option<AsnNameResponse> {
+        self.entries
+            .iter()
+            .find_map(|entry| entry.provider.autsys_name(asn))
+    }
+
+    pub fn holder(&self, opaque_id: &str) -> Option<HolderResponse> {
+        self.entries
+            .iter()
+            .find_map(|entry| entry.provider.holder(opaque_id))
+    }
+
+    pub fn sources(&self, id: &str) -> Option<&Vec<ProviderSource>> {
+        self.get(id).map(|provider| provider.sources())
+    }
+
+    pub fn stats(&self, id: &str) -> Option<ProviderStats> {
+        self.get(id).map(|provider| provider.stats())
+    }
+
+    pub fn skipped(&self, id: &str) -> Option<&SkippedLines> {
+        self.get(id).and_then(|provider| provider.skipped())
+    }
+
+    pub fn status(&self, id: &str) -> Option<&ProviderStatus> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| &entry.status)
+    }
+
+    /// Records the outcome of a source download, `None` clears the previous error.
+    pub fn set_source_error(&mut self, id: &str, filepath: &str, error: Option<String>) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) {
+            match error {
+                Some(error) => entry
+                    .status
+                    .source_errors
+                    .insert(filepath.to_string(), error),
+                None => entry.status.source_errors.remove(filepath),
+            };
+        }
+    }
+
+    /// Takes the fingerprints of the sources of the provider again, after they were found unchanged.
+    ///
+    /// Returns whether they differ from the stored ones, i.e. whether the snapshot is out of date.
+    pub fn refresh_fingerprints(&mut self, id: &str) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => {
+                let fingerprints = snapshot::fingerprints(entry.provider.sources());
+                let changed = fingerprints != entry.fingerprints;
+                entry.fingerprints = fingerprints;
+                changed
+            }
+            None => false,
+        }
+    }
+
+    /// Rebuilds the provider from its (already downloaded) sources and swaps it in.
+    ///
+    /// The provider is parsed on a blocking thread without holding the lock, so lookups keep
+    /// being served from the old data. If the load fails, the old data is kept.
+    pub async fn reload(providers: &Arc<RwLock<Providers>>, id: &str) -> Result<(), LoadError> {
+        let (load, sources) = {
+            let providers = providers.read().await;
+            match providers.entries.iter().find(|entry| entry.id == id) {
+                Some(entry) => (entry.load, entry.provider.sources().clone()),
+                None => return Ok(()),
+            }
+        };
+        let fingerprints = snapshot::fingerprints(&sources);
+
+        let started = Instant::now();
+        let new = match parse(load, sources).await {
+            Ok(new) => new,
+            Err(error) => {
+                if let Some(entry) = providers
+                    .write()
+                    .await
+                    .entries
+                    .iter_mut()
+                    .find(|entry| entry.id == id)
+                {
+                    entry.status.last_error = Some(error.to_string());
+                }
+                return Err(error);
+            }
+        };
+        let load_duration = started.elapsed();
+        log_skipped(id, new.as_ref());
+
+        // The old provider is dropped only after the write guard has been released
+        let old = {
+            let mut providers = providers.write().await;
+            providers.generation += 1;
+            match providers.entries.iter_mut().find(|entry| entry.id == id) {
+                Some(entry) => {
+                    entry.status.loaded_at = Some(SystemTime::now());
+                    entry.status.load_duration = Some(load_duration);
+                    entry.status.last_error = None;
+                    entry.status.from_snapshot = false;
+                    entry.fingerprints = fingerprints;
+                    mem::replace(&mut entry.provider, Arc::from(new))
+                }
+                None => Arc::from(new),
+            }
+        };
+        drop(old);
+
+        Ok(())
+    }
+
+    /// Parses the versions of the sources which were in use on the day (since the unix epoch).
+    ///
+    /// Only the providers which can list their ranges are parsed, the ones without all of their
+    /// sources archived from the day or before it (or failing to load) are returned by their ids.
+    /// The last few days are kept parsed until the next reload.
+    pub async fn load_archived(
+        providers: &Arc<RwLock<Providers>>,
+        day: i64,
+    ) -> Arc<ArchivedProviders> {
+        let (generation, cache) = {
+            let providers = providers.read().await;
+            (providers.generation, providers.archived.clone())
+        };
+
+        // Held while parsing, so that the same day asked for again in the meantime is parsed once
+        let mut cache = cache.lock().await;
+        cache.retain(|archived| archived.generation == generation);
+        if let Some(i) = cache.iter().position(|archived| archived.day == day) {
+            let archived = cache.remove(i);
+            cache.insert(0, archived.clone());
+            return archived;
+        }
+
+        let entries = {
+            let providers = providers.read().await;
+            providers
+                .entries
+                .iter()
+                .filter(|entry| entry.provider.ranges().is_some())
+                .map(|entry| {
+                    (
+                        entry.id.clone(),
+                        entry.load,
+                        entry.provider.sources().to_vec(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Looking the versions up reads the archive's directories, so it's kept off the runtime's workers
+        let candidates = tokio::task::spawn_blocking(move || {
+            entries
+                .into_iter()
+                .map(|(id, load, sources)| {
+                    let archived = sources
+                        .iter()
+                        .map(|source| {
+                            let (day, path) = archive::find(source, day)?;
+                            Some((
+                                day,
+                                ProviderSource {
+                                    filepath: path.to_str()?.to_string(),
+                                    url: None,
+                                    max_time: source.max_time,
+                                    verify: None,
+                                    offline: true,
+                                    archive: None,
+                                },
+                            ))
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    (id, load, archived)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap();
+
+        let mut archived = Vec::new();
+        let mut missing = Vec::new();
+        for (id, load, sources) in candidates {
+            let (days, sources): (Vec<_>, Vec<_>) = match sources {
+                Some(sources) if !sources.is_empty() => sources.into_iter().unzip(),
+                _ => {
+                    missing.push(id);
+                    continue;
+                }
+            };
+
+            match parse(load, sources).await {
+                Ok(provider) => archived.push(ArchivedProvider {
+                    id,
+                    day: days.into_iter().min().unwrap(),
+                    provider,
+                }),
+                Err(error) => {
+                    error!("Failed to load archived provider {}! ({})", id, error);
+                    missing.push(id);
+                }
+            }
+        }
+
+        let archived = Arc::new(ArchivedProviders {
+            day,
+            generation,
+            providers: archived,
+            missing,
+        });
+        cache.insert(0, archived.clone());
+        cache.truncate(ARCHIVE_CACHE_DAYS);
+
+        archived
+    }
+
+    /// Same as `save_snapshot`, but from a clone of the providers on a blocking thread, so that
+    /// neither the lookups nor the runtime wait for the encoding and the write.
+    pub async fn save_snapshot_unlocked(providers: &Arc<RwLock<Providers>>) {
+        let providers = providers.read().await.clone();
+        if providers.snapshot.is_none() {
+            return;
+        }
+
+        if let Err(error) = tokio::task::spawn_blocking(move || providers.save_snapshot()).await {
+            error!("Failed to save the provider snapshot! ({})", error);
+        }
+    }
+
+    /// Writes the parsed data of the providers to the snapshot, if one is configured.
+    ///
+    /// The providers without any data or with missing sources are left out, so they get parsed on the next start.
+    pub fn save_snapshot(&self) {
+        let path = match &self.snapshot {
+            Some(path) => path,
+            None => return,
+        };
+
+        let started = Instant::now();
+        let encoded = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                Some((
+                    entry,
+                    entry.fingerprints.clone()?,
+                    entry.provider.snapshot()?,
+                ))
+            })
+            .collect::<Vec<_>>();
+        let snapshot_providers = encoded
+            .iter()
+            .map(|(entry, sources, data)| ProviderSnapshot {
+                id: entry.id.clone(),
+                kind: entry.kind.clone(),
+                sources: sources.clone(),
+                data,
+            })
+            .collect();
+
+        match snapshot::write(path, snapshot_providers) {
+            Ok(_) => info!(
+                "Saved the provider snapshot to {} in {}ms!",
+                path.display(),
+                started.elapsed().as_millis()
+            ),
+            Err(error) => error!(
+                "Failed to save the provider snapshot to {}! ({})",
+                path.display(),
+                error
+            ),
+        }
+    }
+}
+
+// A missing snapshot is expected on the first start, a broken one is only logged and parsed over
+fn open_snapshot(path: &Path) -> Option<Snapshot> {
+    match Snapshot::open(path) {
+        Ok(snapshot) => Some(snapshot),
+        Err(SnapshotError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => {
+            warn!(
+                "Ignoring the provider snapshot {}! ({})",
+                path.display(),
+                error
+            );
+            None
+        }
+    }
+}
+
+/// Ids of the providers in the config, a provider is any table under providers with sources set.
+pub fn configured_providers(config: &Config) -> Vec<String> {
+    fn walk(prefix: &str, table: config::Map<String, config::Value>, ids: &mut Vec<String>) {
+        for (key, value) in table {
+            let id = match prefix.is_empty() {
+                true => key,
+                false => concat_string!(prefix, ".", key),
+            };
+
+            if let Ok(table) = value.into_table() {
+                match table.contains_key("sources") {
+                    true => ids.push(id),
+                    false => walk(&id, table, ids),
+                }
+            }
+        }
+    }
+
+    let mut ids = Vec::new();
+    if let Ok(table) = config.get_table("providers") {
+        walk("", table, &mut ids);
+    }
+
+    // The config tables aren't ordered
+    ids.sort();
+    ids
+}
+
+fn load_precedence(config: &Config) -> HashMap<LookupKind, Vec<String>> {
+    LookupKind::ALL
+        .iter()
+        .map(|kind| {
+            let ids = match config.get_array(&concat_string!("lookup.", kind.id())) {
+                Ok(ids) => ids
+                    .into_iter()
+                    .map(|id| {
+                        id.into_string().expect(
+                            "Invalid config (lookup precedence must be a list of provider ids)!",
+                        )
+                    })
+                    .collect(),
+                Err(_) => kind
+                    .default_precedence()
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect(),
+            };
+
+            (*kind, ids)
+        })
+        .collect()
+}
+
+/// Reads the whole (binary) source file into memory.
+pub async fn read_source_bytes(source: &ProviderSource) -> Result<Vec<u8>, LoadError> {
+    fs::read(Path::new(&source.filepath))
+        .await
+        .map_err(|error| LoadError::Io {
+            filepath: source.filepath.clone(),
+            error,
+        })
+}
+
+/// Reads the whole source file into memory.
+pub async fn read_source(source: &ProviderSource) -> Result<String, LoadError> {
+    fs::read_to_string(Path::new(&source.filepath))
+        .await
+        .map_err(|error| LoadError::Io {
+            filepath: source.filepath.clone(),
+            error,
+        })
+}
+
+/// Longest-prefix-match indexes for both address families, the values are positions in the provider's values.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CidrIndex {
+    pub v4: PrefixIndex<u32, u32>,
+    pub v6: PrefixIndex<u128, u32>,
+}
+
+impl CidrIndex {
+    pub fn build<'a>(cidrs: impl Iterator<Item = &'a CIDR>) -> Self {
+        CidrIndex::from_ranges(cidrs.map(|cidr| (cidr.first_address(), cidr.last_address())))
+    }
+
+    /// Builds the index from inclusive address ranges, the values are positions in the iterator.
+    pub fn from_ranges(ranges: impl Iterator<Item = (IpAddr, IpAddr)>) -> Self {
+        let mut v4_ranges = Vec::new();
+        let mut v6_ranges = Vec::new();
+
+        for (i, range) in ranges.enumerate() {
+            match range {
+                (IpAddr::V4(start), IpAddr::V4(end)) => {
+                    v4_ranges.push((start.to_bits(), end.to_bits(), i as u32))
+                }
+                (IpAddr::V6(start), IpAddr::V6(end)) => {
+                    v6_ranges.push((start.to_bits(), end.to_bits(), i as u32))
+                }
+                _ => error!("Skipping range spanning both address families!"),
+            }
+        }
+
+        CidrIndex {
+            v4: PrefixIndex::build(v4_ranges),
+            v6: PrefixIndex::build(v6_ranges),
+        }
+    }
+
+    pub fn lookup(&self, address: IpAddr) -> Option<u32> {
+        match address {
+            IpAddr::V4(address) => self.v4.lookup(address.to_bits()),
+            // IPv4-mapped addresses are looked up as plain IPv4 addresses
+            IpAddr::V6(address) => match address.to_ipv4_mapped() {
+                Some(address) => self.v4.lookup(address.to_bits()),
+                None => self.v6.lookup(address.to_bits()),
             },
         }
     }
 }
 
 pub fn load_provider_sources(config: &Config, provider: &str) -> Option<Vec<ProviderSource>> {
+    let offline = config.get_bool("providers.offline").unwrap_or(false);
+    let archive = config
+        .get_string("providers.archive")
+        .ok()
+        .map(|directory| SourceArchive {
+            directory,
+            max_age: config
+                .get_int("providers.archive_max_age")
+                .unwrap_or(7776000) // default 90 days
+                .try_into()
+                .expect("Invalid config (providers.archive_max_age must be a positive integer)!"),
+        });
+
     match config.get_array(&concat_string!("providers.", provider, ".sources")) {
         Ok(sources) => {
             let mut parsed_sources = Vec::new();
@@ -163,12 +1440,11 @@ pub fn load_provider_sources(config: &Config, provider: &str) -> Option<Vec<Prov
                     .to_owned()
                     .into_string()
                     .unwrap();
-                let url = source_map
-                    .get("url")
-                    .expect("Invalid config (provider source must have a url set)!")
-                    .to_owned()
-                    .into_string()
-                    .unwrap();
+                let url = source_map.get("url").map(|url| {
+                    url.to_owned()
+                        .into_string()
+                        .expect("Invalid config (url of provider source must be a string)!")
+                });
                 let max_time = source_map
                     .get("max_time")
                     .unwrap_or(&config::Value::from(2592000)) // default 1 month
@@ -177,11 +1453,31 @@ pub fn load_provider_sources(config: &Config, provider: &str) -> Option<Vec<Prov
                     .expect(
                         "Invalid config (max_time for provider source must be a valid integer)!",
                     ) as u32;
+                let verify = match source_map
+                    .get("verify")
+                    .map(|verify| verify.to_owned().into_string().unwrap())
+                    .as_deref()
+                {
+                    Some("md5") => Some(SourceVerification::Md5),
+                    Some("asc") => Some(SourceVerification::Asc {
+                        keyring: source_map
+                            .get("keyring")
+                            .expect("Invalid config (provider source verified with asc must have a keyring set)!")
+                            .to_owned()
+                            .into_string()
+                            .unwrap(),
+                    }),
+                    Some(verify) => panic!("Invalid config (unknown source verification {})!", verify),
+                    None => None,
+                };
 
                 parsed_sources.push(ProviderSource {
                     filepath,
                     url,
                     max_time,
+                    verify,
+                    offline,
+                    archive: archive.clone(),
                 });
             }
             Some(parsed_sources)
@@ -189,3 +1485,261 @@ pub fn load_provider_sources(config: &Config, provider: &str) -> Option<Vec<Prov
         Err(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+    use config::Config;
+    use mtilib::types::{AllocationState, Rir};
+    use tokio::sync::RwLock;
+
+    use super::{
+        bogons::BogonsProvider,
+        iana::reserved::{ReservedEntry, ReservedProvider},
+        stats::{StatsEntry, StatsProvider},
+        CidrIndex, LoadError, LookupKind, Provider, ProviderSource, Providers,
+    };
+    use crate::utils::CIDR;
+
+    #[tokio::test]
+    async fn test_reload_keeps_old_data_on_failure() {
+        let providers = Arc::new(RwLock::new(Providers::empty()));
+        {
+            let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+            stats.values.push(StatsEntry {
+                cidr: CIDR::from_str("1.0.0.0/8").unwrap(),
+                allocation_state: AllocationState::Allocated,
+                rir: Rir::Apnic,
+                country: None,
+                registration_date: None,
+                opaque_id: None,
+            });
+            stats
+                .sources
+                .push(ProviderSource::local("./does/not/exist.txt"));
+            providers.write().await.insert("arin.stats", stats);
+        }
+
+        assert!(matches!(
+            Providers::reload(&providers, "arin.stats").await,
+            Err(LoadError::Io { .. })
+        ));
+        assert_eq!(
+            providers.read().await.stats("arin.stats").unwrap().entries,
+            1
+        );
+        assert!(providers
+            .read()
+            .await
+            .status("arin.stats")
+            .unwrap()
+            .last_error
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_load_failure_keeps_provider() {
+        let config = Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+                [providers]
+                offline = true
+
+                [[providers.arin.stats.sources]]
+                filepath = "./does/not/exist.txt"
+                max_time = 0
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap();
+
+        // The provider stays registered without any data, so that a later reload can fill it
+        let providers = Providers::load(&config).await;
+        assert_eq!(providers.ids(), vec![String::from("arin.stats")]);
+        assert_eq!(providers.stats("arin.stats").unwrap().entries, 0);
+        assert_eq!(providers.sources("arin.stats").unwrap().len(), 1);
+
+        let status = providers.status("arin.stats").unwrap();
+        assert!(status.loaded_at.is_none());
+        assert!(status.last_error.as_ref().unwrap().contains("exist.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_load_from_snapshot() {
+        let directory =
+            std::env::temp_dir().join(format!("diglett-providers-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("apnic.txt");
+        std::fs::copy(
+            ProviderSource::fixture("delegated-apnic-malformed.txt").filepath,
+            &source,
+        )
+        .unwrap();
+
+        let config = Config::builder()
+            .add_source(config::File::from_str(
+                &format!(
+                    r#"
+                    [providers]
+                    offline = true
+                    snapshot = "{}"
+
+                    [[providers.arin.stats.sources]]
+                    filepath = "{}"
+                    max_time = 0
+                    "#,
+                    directory.join("snapshot.bin").display(),
+                    source.display()
+                ),
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap();
+
+        let parsed = Providers::load(&config).await;
+        assert!(!parsed.status("arin.stats").unwrap().from_snapshot);
+
+        // Unchanged sources are restored along with their skipped lines
+        let restored = Providers::load(&config).await;
+        assert!(restored.status("arin.stats").unwrap().from_snapshot);
+        assert_eq!(
+            restored.stats("arin.stats").unwrap().entries,
+            parsed.stats("arin.stats").unwrap().entries
+        );
+        assert_eq!(restored.skipped("arin.stats").unwrap().count, 6);
+        assert_eq!(
+            restored.sources("arin.stats").unwrap()[0].filepath,
+            source.to_str().unwrap()
+        );
+        let address = IpAddr::from_str("1.0.0.1").unwrap();
+        assert_eq!(restored.address(address).rir, parsed.address(address).rir);
+
+        // A changed source is parsed again
+        let mut contents = std::fs::read_to_string(&source).unwrap();
+        contents.push_str("apnic|JP|ipv4|1.0.16.0|4096|20110412|allocated\n");
+        std::fs::write(&source, contents).unwrap();
+        let reparsed = Providers::load(&config).await;
+        assert!(!reparsed.status("arin.stats").unwrap().from_snapshot);
+        assert_eq!(
+            reparsed.stats("arin.stats").unwrap().entries,
+            parsed.stats("arin.stats").unwrap().entries + 1
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lookup_precedence() {
+        let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+        stats.values.push(StatsEntry {
+            cidr: CIDR::from_str("10.0.0.0/8").unwrap(),
+            allocation_state: AllocationState::Allocated,
+            rir: Rir::Arin,
+            country: None,
+            registration_date: None,
+            opaque_id: None,
+        });
+        stats.index = CidrIndex::build(stats.values.iter().map(|x| &x.cidr));
+
+        let mut reserved = ReservedProvider::load_sources(vec![]).await.unwrap();
+        reserved.values.push(ReservedEntry {
+            cidr: CIDR::from_str("10.0.0.0/8").unwrap(),
+            name: String::from("Private-Use"),
+            rfc: String::from("[RFC1918]"),
+            source: Some(true),
+            destination: Some(true),
+            forwardable: Some(true),
+            globally_reachable: Some(false),
+        });
+        reserved.index = CidrIndex::build(reserved.values.iter().map(|x| &x.cidr));
+
+        let mut providers = Providers::empty();
+        providers.insert("arin.stats", stats);
+        providers.insert("iana.reserved", reserved);
+
+        let address = IpAddr::from_str("10.0.0.1").unwrap();
+        assert_eq!(
+            providers.allocation_state(address),
+            AllocationState::Reserved
+        );
+
+        providers.precedence.insert(
+            LookupKind::AllocationState,
+            vec![String::from("arin.stats"), String::from("iana.reserved")],
+        );
+        assert_eq!(
+            providers.allocation_state(address),
+            AllocationState::Allocated
+        );
+        assert_eq!(providers.rir(address), Some(Rir::Arin));
+    }
+
+    #[tokio::test]
+    async fn test_multicast_and_bogons() {
+        let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+        stats.values.push(StatsEntry {
+            cidr: CIDR::from_str("100.64.0.0/16").unwrap(),
+            allocation_state: AllocationState::Allocated,
+            rir: Rir::Arin,
+            country: None,
+            registration_date: None,
+            opaque_id: None,
+        });
+        stats.index = CidrIndex::build(stats.values.iter().map(|x| &x.cidr));
+
+        let bogons =
+            BogonsProvider::load_sources(vec![ProviderSource::fixture("fullbogons-ipv4.txt")])
+                .await
+                .unwrap();
+        assert_eq!(bogons.values.len(), 5);
+
+        let mut providers = Providers::empty();
+        providers.insert(
+            "iana.reserved",
+            ReservedProvider::load_sources(vec![]).await.unwrap(),
+        );
+        providers.insert("arin.stats", stats);
+        providers.insert("bogons.full", bogons);
+
+        for (address, state) in [
+            ("224.0.0.1", AllocationState::Multicast),
+            ("ff02::1", AllocationState::Multicast),
+            ("100.127.0.1", AllocationState::Bogon),
+            // The RIR stats come first
+            ("100.64.0.1", AllocationState::Allocated),
+            ("8.8.8.8", AllocationState::Unknown),
+        ] {
+            assert_eq!(
+                providers.allocation_state(IpAddr::from_str(address).unwrap()),
+                state
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archived_cache() {
+        let providers = Arc::new(RwLock::new(Providers::fixture().await));
+
+        // Nothing is archived, but the result is kept all the same
+        let archived = Providers::load_archived(&providers, 19753).await;
+        assert!(archived.providers.is_empty());
+        assert!(archived.missing.contains(&String::from("arin.stats")));
+        assert!(Arc::ptr_eq(
+            &archived,
+            &Providers::load_archived(&providers, 19753).await
+        ));
+        assert!(!Arc::ptr_eq(
+            &archived,
+            &Providers::load_archived(&providers, 19754).await
+        ));
+
+        // A reload may have archived new versions
+        Providers::reload(&providers, "arin.stats").await.unwrap();
+        assert!(!Arc::ptr_eq(
+            &archived,
+            &Providers::load_archived(&providers, 19753).await
+        ));
+    }
+}
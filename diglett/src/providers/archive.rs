@@ -0,0 +1,255 @@
+// Dated archives of the source files
+//
+// Every version of a source file is hard linked (or copied, across file systems) to
+// `<directory>/<YYYY-MM-DD>/<filepath>` once it's in place, dated by its modification time, which is
+// when it was downloaded or when the imported file was created. The latest archived version from a
+// date or before it is the version which was in use on that date. A later version from the same day
+// replaces the earlier one. Of the versions older than the max age, only the latest one is kept per
+// source, it was still in use at the cutoff (a rarely updated file may have no later version at all).
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, warn};
+
+use crate::{providers::ProviderSource, utils};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Clone, Debug)]
+pub struct SourceArchive {
+    pub directory: String,
+    /// Seconds the archived versions are kept for
+    pub max_age: u64,
+}
+
+fn days_since_epoch(time: SystemTime) -> i64 {
+    (time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+        / SECONDS_PER_DAY) as i64
+}
+
+// Only the normal components are kept, so that "./data" and "../data" stay inside the archive
+fn archived_path(archive: &SourceArchive, date: i64, filepath: &str) -> PathBuf {
+    let mut path = Path::new(&archive.directory).join(utils::format_date(date));
+    path.extend(
+        Path::new(filepath)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_))),
+    );
+    path
+}
+
+fn archived_dates(archive: &SourceArchive) -> Vec<i64> {
+    let mut dates = match fs::read_dir(&archive.directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| utils::parse_date(entry.ok()?.file_name().to_str()?))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+
+    dates.sort_unstable();
+    dates
+}
+
+fn prune(source: &ProviderSource, archive: &SourceArchive) -> io::Result<()> {
+    let oldest = days_since_epoch(SystemTime::now()) - (archive.max_age / SECONDS_PER_DAY) as i64;
+
+    let mut dates = archived_dates(archive)
+        .into_iter()
+        .take_while(|date| *date <= oldest)
+        .filter(|date| archived_path(archive, *date, &source.filepath).exists())
+        .collect::<Vec<_>>();
+    // The version in use at the cutoff
+    dates.pop();
+
+    for date in dates {
+        debug!(
+            "Removing archived file {} from {}!",
+            source.filepath,
+            utils::format_date(date)
+        );
+        let path = archived_path(archive, date, &source.filepath);
+        fs::remove_file(&path)?;
+
+        // The directories left empty go too, up to the date's one
+        let date_directory = Path::new(&archive.directory).join(utils::format_date(date));
+        for directory in path.ancestors().skip(1) {
+            if !directory.starts_with(&date_directory) || fs::remove_dir(directory).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn try_archive(source: &ProviderSource, archive: &SourceArchive) -> io::Result<()> {
+    let date = days_since_epoch(fs::metadata(&source.filepath)?.modified()?);
+    let path = archived_path(archive, date, &source.filepath);
+    if let Some(prefix) = path.parent() {
+        fs::create_dir_all(prefix)?;
+    }
+
+    // Linking fails when the path exists, the later version of the day wins
+    let _ = fs::remove_file(&path);
+    if fs::hard_link(&source.filepath, &path).is_err() {
+        fs::copy(&source.filepath, &path)?;
+    }
+
+    prune(source, archive)
+}
+
+/// Archives the current version of the source file, if the source has an archive.
+///
+/// Failing to archive is only logged, it doesn't stop the file from being used.
+pub fn archive(source: &ProviderSource) {
+    if let Some(archive) = source.archive.as_ref() {
+        if let Err(error) = try_archive(source, archive) {
+            warn!("Failed to archive file {}! ({})", source.filepath, error);
+        }
+    }
+}
+
+/// Archives the current version of the source file before it's replaced, unless it has been archived already.
+///
+/// Only matters for the files which were there before the archive was set up, the later versions
+/// get archived as soon as they're in place.
+pub fn archive_previous(source: &ProviderSource) {
+    if source.archive.is_some()
+        && Path::new(&source.filepath).exists()
+        && find(source, i64::MAX).is_none()
+    {
+        archive(source);
+    }
+}
+
+/// The latest archived version of the source from the day (since the unix epoch) or before it, with its day.
+pub fn find(source: &ProviderSource, day: i64) -> Option<(i64, PathBuf)> {
+    let archive = source.archive.as_ref()?;
+
+    archived_dates(archive)
+        .into_iter()
+        .rev()
+        .filter(|date| *date <= day)
+        .map(|date| (date, archived_path(archive, date, &source.filepath)))
+        .find(|(_, path)| path.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        time::{Duration, SystemTime},
+    };
+
+    use super::{archive, archive_previous, find, SourceArchive};
+    use crate::{providers::ProviderSource, utils};
+
+    #[test]
+    fn test_archive() {
+        let directory =
+            std::env::temp_dir().join(format!("diglett-archive-{}", std::process::id()));
+        let filepath = directory.join("data/arin.txt");
+        fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+
+        let source = ProviderSource {
+            archive: Some(SourceArchive {
+                directory: directory.join("archive").to_str().unwrap().to_string(),
+                max_age: 30 * 86400,
+            }),
+            ..ProviderSource::local(filepath.to_str().unwrap())
+        };
+
+        // A new file each time, same as the downloads, the archived versions are links to the old ones
+        let write = |contents: &str, days_ago: u64| {
+            let _ = fs::remove_file(&filepath);
+            fs::write(&filepath, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(&filepath)
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(days_ago * 86400))
+                .unwrap();
+        };
+        let today = (SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / 86400) as i64;
+
+        // Of the versions from before the max age, the ones older than the latest get removed as
+        // soon as something is archived
+        write("ancient", 60);
+        archive_previous(&source);
+        write("older", 40);
+        archive_previous(&source);
+        archive(&source);
+        write("old", 10);
+        archive(&source);
+        assert!(find(&source, today - 60).is_none());
+        assert!(!directory
+            .join("archive")
+            .join(utils::format_date(today - 60))
+            .exists());
+        write("new", 0);
+        archive_previous(&source);
+        archive(&source);
+
+        let (date, path) = find(&source, today - 35).unwrap();
+        assert_eq!(date, today - 40);
+        assert_eq!(fs::read_to_string(path).unwrap(), "older");
+        let (date, path) = find(&source, today - 5).unwrap();
+        assert_eq!(date, today - 10);
+        assert_eq!(fs::read_to_string(path).unwrap(), "old");
+        let (date, path) = find(&source, today).unwrap();
+        assert_eq!(date, today);
+        assert_eq!(fs::read_to_string(path).unwrap(), "new");
+        assert!(find(&source, today - 41).is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_archive_keeps_only_version() {
+        let directory =
+            std::env::temp_dir().join(format!("diglett-archive-only-{}", std::process::id()));
+        let filepath = directory.join("data/reserved.csv");
+        fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+
+        let source = ProviderSource {
+            archive: Some(SourceArchive {
+                directory: directory.join("archive").to_str().unwrap().to_string(),
+                max_age: 30 * 86400,
+            }),
+            ..ProviderSource::local(filepath.to_str().unwrap())
+        };
+        fs::write(&filepath, "reserved").unwrap();
+        File::options()
+            .write(true)
+            .open(&filepath)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(100 * 86400))
+            .unwrap();
+        let today = (SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / 86400) as i64;
+
+        // Pruned each time it's archived, without a newer version to replace it
+        archive(&source);
+        archive(&source);
+
+        let (date, path) = find(&source, today).unwrap();
+        assert_eq!(date, today - 100);
+        assert_eq!(fs::read_to_string(path).unwrap(), "reserved");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
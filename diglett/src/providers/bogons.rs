@@ -0,0 +1,109 @@
+// Bogon lists, one prefix per line (Team Cymru fullbogons-ipv4.txt and fullbogons-ipv6.txt)
+//
+// The full bogons contain both the reserved and the unallocated address space, which is why the
+// provider comes after the RIR stats in the default lookup order.
+
+use std::{net::IpAddr, str::FromStr};
+
+use mtilib::types::AllocationState;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, Provider, ProviderSource,
+        ProviderStats, SkippedLines,
+    },
+    utils::CIDR,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct BogonsProvider {
+    pub values: Vec<CIDR>,
+    pub index: CidrIndex,
+    #[serde(skip)]
+    pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
+}
+
+impl BogonsProvider {
+    pub fn lookup(&self, address: IpAddr) -> Option<&CIDR> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
+}
+
+impl Provider for BogonsProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading bogons...");
+
+        let mut bogons = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents_str = providers::read_source(source).await?;
+
+            for (i, line) in contents_str.lines().map(|line| line.trim()).enumerate() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                match CIDR::from_str(line) {
+                    Ok(cidr) => bogons.push(cidr),
+                    Err(error) => skipped.skip(source, i + 1, format!("{} ({})", error, line)),
+                }
+            }
+        }
+
+        info!("Loaded bogons!");
+
+        Ok(BogonsProvider {
+            index: CidrIndex::build(bogons.iter()),
+            values: bogons,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: BogonsProvider = providers::snapshot::decode(data)?;
+        Some(BogonsProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::AllocationState => self
+                .lookup(address)
+                .map(|_| LookupValue::AllocationState(AllocationState::Bogon)),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|cidr| (cidr.first_address(), cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
+        }
+    }
+}
@@ -0,0 +1,535 @@
+// Downloading of the provider sources
+//
+// Files are streamed into a temporary file next to the target and only renamed over it once they
+// have been fully downloaded (and verified), so a failed download always leaves the last good file.
+// The file itself is only ever replaced, when it was last found up to date is kept next to it with
+// the validators, so that its modification time stays the one of the download.
+// The replaced file and the new one are kept in the archive of the source, when it has one.
+
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use md5::{Digest, Md5};
+use regex::Regex;
+use reqwest::{
+    header::{HeaderMap, HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+use tracing::{debug, warn};
+
+use super::{archive, ProviderSource};
+
+const DOWNLOAD_USER_AGENT: &str = concat!("diglett/", env!("CARGO_PKG_VERSION"));
+const DOWNLOAD_ATTEMPTS: u32 = 4;
+const DOWNLOAD_BACKOFF: Duration = Duration::from_secs(2);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+const HASH_CHUNK_SIZE: usize = 1 << 16;
+
+/// How a downloaded source gets verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceVerification {
+    /// Compare against the MD5 checksum published at `<url>.md5`
+    Md5,
+    /// Check the PGP signature published at `<url>.asc` against the keys in the keyring (uses `gpgv`)
+    Asc { keyring: String },
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Offline,
+    Request(reqwest::Error),
+    Status(StatusCode),
+    Io(std::io::Error),
+    MissingChecksum,
+    Checksum { expected: String, actual: String },
+    Signature(String),
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Offline => write!(f, "source is offline or has no url"),
+            DownloadError::Request(error) => write!(f, "request failed: {}", error),
+            DownloadError::Status(status) => write!(f, "unexpected status {}", status),
+            DownloadError::Io(error) => write!(f, "io error: {}", error),
+            DownloadError::MissingChecksum => write!(f, "no checksum in the checksum file"),
+            DownloadError::Checksum { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {}, got {})",
+                    expected, actual
+                )
+            }
+            DownloadError::Signature(error) => write!(f, "bad signature: {}", error),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(error: reqwest::Error) -> Self {
+        DownloadError::Request(error)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(error: std::io::Error) -> Self {
+        DownloadError::Io(error)
+    }
+}
+
+impl DownloadError {
+    // Only network errors and server errors are worth another try
+    fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Request(_) => true,
+            DownloadError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    Updated,
+    NotModified,
+}
+
+// Validators of the last successful download, stored next to the file
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SourceMeta {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Unix timestamp of when the file was last downloaded or found unchanged
+    #[serde(default)]
+    checked: Option<u64>,
+}
+
+fn meta_path(source: &ProviderSource) -> String {
+    concat_string!(source.filepath, ".meta")
+}
+
+fn temp_path(source: &ProviderSource) -> String {
+    concat_string!(source.filepath, ".tmp")
+}
+
+async fn load_meta(source: &ProviderSource) -> SourceMeta {
+    // Without the file the validators are useless
+    if !Path::new(&source.filepath).exists() {
+        return SourceMeta::default();
+    }
+
+    match fs::read_to_string(meta_path(source)).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => SourceMeta::default(),
+    }
+}
+
+// Marks the current file as fresh, so that it isn't checked again until max_time passes
+async fn save_meta(source: &ProviderSource, mut meta: SourceMeta) {
+    meta.checked = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs());
+
+    if let Err(error) = fs::write(meta_path(source), serde_json::to_vec(&meta).unwrap()).await {
+        warn!(
+            "Failed to save the validators of file {}! ({})",
+            source.filepath, error
+        );
+    }
+}
+
+/// When the file was last downloaded or found unchanged, `None` when it never was (e.g. imported files).
+pub async fn last_checked(source: &ProviderSource) -> Option<SystemTime> {
+    load_meta(source)
+        .await
+        .checked
+        .map(|checked| UNIX_EPOCH + Duration::from_secs(checked))
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+// None when there's nothing published at the url
+async fn fetch_text(client: &reqwest::Client, url: &str) -> Result<Option<String>, DownloadError> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return match fs::read_to_string(path).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        };
+    }
+
+    let response = client.get(url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(DownloadError::Status(response.status()));
+    }
+
+    Ok(Some(response.text().await?))
+}
+
+async fn verify_md5(
+    client: &reqwest::Client,
+    url: &str,
+    temp_path: &str,
+) -> Result<(), DownloadError> {
+    // The checksum files come in both the "MD5 (file) = hash" and "hash  file" flavours
+    let checksum_url = concat_string!(url, ".md5");
+    let checksum_file = match fetch_text(client, &checksum_url).await? {
+        Some(checksum_file) => checksum_file,
+        None => {
+            warn!(
+                "No checksum at {}, keeping the file unverified!",
+                checksum_url
+            );
+            return Ok(());
+        }
+    };
+    let expected = match Regex::new(r"\b[\da-fA-F]{32}\b")
+        .unwrap()
+        .find(&checksum_file)
+    {
+        Some(expected) => expected.as_str().to_lowercase(),
+        None => return Err(DownloadError::MissingChecksum),
+    };
+
+    let mut file = fs::File::open(temp_path).await?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; HASH_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    let actual = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    match expected == actual {
+        true => Ok(()),
+        false => Err(DownloadError::Checksum { expected, actual }),
+    }
+}
+
+async fn verify_asc(
+    client: &reqwest::Client,
+    url: &str,
+    temp_path: &str,
+    keyring: &str,
+) -> Result<(), DownloadError> {
+    let signature_url = concat_string!(url, ".asc");
+    let signature = match fetch_text(client, &signature_url).await? {
+        Some(signature) => signature,
+        None => {
+            warn!(
+                "No signature at {}, keeping the file unverified!",
+                signature_url
+            );
+            return Ok(());
+        }
+    };
+    let signature_path = concat_string!(temp_path, ".asc");
+    fs::write(&signature_path, signature).await?;
+
+    let output = Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(&signature_path)
+        .arg(temp_path)
+        .output()
+        .await;
+    let _ = fs::remove_file(&signature_path).await;
+
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(DownloadError::Signature(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+        Err(error) => Err(DownloadError::Signature(format!(
+            "failed to run gpgv ({})",
+            error
+        ))),
+    }
+}
+
+// Local files are only copied when they have been modified after the current file
+async fn is_up_to_date(path: &str, filepath: &str) -> std::io::Result<bool> {
+    let current = match fs::metadata(filepath).await {
+        Ok(metadata) => metadata.modified()?,
+        Err(_) => return Ok(false),
+    };
+
+    Ok(fs::metadata(path).await?.modified()? <= current)
+}
+
+async fn try_download(
+    client: &reqwest::Client,
+    source: &ProviderSource,
+    url: &str,
+) -> Result<DownloadOutcome, DownloadError> {
+    let temp_path = temp_path(source);
+
+    let new_meta = match url.strip_prefix("file://") {
+        Some(path) => {
+            if is_up_to_date(path, &source.filepath).await? {
+                save_meta(source, SourceMeta::default()).await;
+                return Ok(DownloadOutcome::NotModified);
+            }
+
+            fs::copy(path, &temp_path).await?;
+            None
+        }
+        None => {
+            let meta = load_meta(source).await;
+
+            let mut request = client.get(url);
+            if let Some(etag) = meta.etag.as_ref() {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = meta.last_modified.as_ref() {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+
+            let mut response = request.send().await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                save_meta(source, meta).await;
+                return Ok(DownloadOutcome::NotModified);
+            }
+            if !response.status().is_success() {
+                return Err(DownloadError::Status(response.status()));
+            }
+
+            let new_meta = SourceMeta {
+                etag: header_value(response.headers(), ETAG),
+                last_modified: header_value(response.headers(), LAST_MODIFIED),
+                checked: None,
+            };
+
+            // Stream the body into the temporary file
+            let mut file = fs::File::create(&temp_path).await?;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.sync_all().await?;
+
+            Some(new_meta)
+        }
+    };
+
+    let verified = match source.verify.as_ref() {
+        Some(SourceVerification::Md5) => verify_md5(client, url, &temp_path).await,
+        Some(SourceVerification::Asc { keyring }) => {
+            verify_asc(client, url, &temp_path, keyring).await
+        }
+        None => Ok(()),
+    };
+    verified?;
+
+    // Archiving links or copies the whole file and prunes the old versions, so it runs on a blocking
+    // thread together with moving the new version into place
+    let archived = source.clone();
+    tokio::task::spawn_blocking(move || {
+        archive::archive_previous(&archived);
+        std::fs::rename(&temp_path, &archived.filepath)?;
+        archive::archive(&archived);
+        Ok::<_, std::io::Error>(())
+    })
+    .await
+    .unwrap()?;
+    // The local files have no validators
+    save_meta(source, new_meta.unwrap_or_default()).await;
+
+    Ok(DownloadOutcome::Updated)
+}
+
+/// Downloads the source, retrying transient failures with an exponential backoff.
+/// On failure the previous file (if any) is left untouched.
+pub async fn download(source: &ProviderSource) -> Result<DownloadOutcome, DownloadError> {
+    let url = match (source.offline, source.url.as_ref()) {
+        (false, Some(url)) => url,
+        _ => return Err(DownloadError::Offline),
+    };
+
+    if let Some(prefix) = Path::new(&source.filepath).parent() {
+        fs::create_dir_all(prefix).await?;
+    }
+
+    let client = reqwest::Client::builder()
+        .user_agent(DOWNLOAD_USER_AGENT)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()?;
+
+    let mut attempt = 1;
+    loop {
+        let result = try_download(&client, source, url).await;
+        if result.is_err() {
+            let _ = fs::remove_file(temp_path(source)).await;
+        }
+
+        match result {
+            Err(error) if error.is_transient() && attempt < DOWNLOAD_ATTEMPTS => {
+                let backoff = DOWNLOAD_BACKOFF * 2u32.pow(attempt - 1);
+                debug!(
+                    "Download of {} failed, retrying in {}s! ({})",
+                    url,
+                    backoff.as_secs(),
+                    error
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use tokio::fs;
+
+    use super::{download, last_checked, DownloadOutcome, SourceVerification};
+    use crate::providers::ProviderSource;
+
+    const CONTENTS: &str = "apnic|AU|ipv4|1.0.0.0|256|20110811|assigned|A91872ED\n";
+
+    async fn serve() -> SocketAddr {
+        let app = Router::new()
+            .route(
+                "/stats",
+                get(|headers: HeaderMap| async move {
+                    match headers.get(header::IF_NONE_MATCH) {
+                        Some(etag) if etag == "\"v1\"" => {
+                            (StatusCode::NOT_MODIFIED, "").into_response()
+                        }
+                        _ => ([(header::ETAG, "\"v1\"")], CONTENTS).into_response(),
+                    }
+                }),
+            )
+            .route("/unverified", get(|| async { "unverified\n" }))
+            .route(
+                "/stats.md5",
+                get(|| async { "MD5 (stats) = 0123456789abcdef0123456789abcdef" }),
+            );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_download_keeps_last_good_file() {
+        let address = serve().await;
+        let directory = std::env::temp_dir().join(format!("diglett-download-{}", address.port()));
+        let mut source = ProviderSource {
+            filepath: directory.join("stats.txt").to_str().unwrap().to_string(),
+            url: Some(format!("http://{}/stats", address)),
+            max_time: 0,
+            verify: None,
+            offline: false,
+            archive: None,
+        };
+
+        assert_eq!(download(&source).await.unwrap(), DownloadOutcome::Updated);
+        assert_eq!(
+            fs::read_to_string(&source.filepath).await.unwrap(),
+            CONTENTS
+        );
+
+        // An unchanged file keeps its modification time, only the time of the check moves on
+        let modified = fs::metadata(&source.filepath)
+            .await
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(
+            download(&source).await.unwrap(),
+            DownloadOutcome::NotModified
+        );
+        assert_eq!(
+            fs::metadata(&source.filepath)
+                .await
+                .unwrap()
+                .modified()
+                .unwrap(),
+            modified
+        );
+        assert!(last_checked(&source).await.is_some());
+
+        // A bad checksum and a missing file must not touch the downloaded file
+        fs::remove_file(concat_string!(source.filepath, ".meta"))
+            .await
+            .unwrap();
+        source.verify = Some(SourceVerification::Md5);
+        assert!(download(&source).await.is_err());
+
+        source.verify = None;
+        source.url = Some(format!("http://{}/missing", address));
+        assert!(download(&source).await.is_err());
+
+        assert_eq!(
+            fs::read_to_string(&source.filepath).await.unwrap(),
+            CONTENTS
+        );
+        assert!(!directory.join("stats.txt.tmp").exists());
+
+        // Local files are copied like the downloaded ones
+        let local_path = directory.join("local.txt").to_str().unwrap().to_string();
+        fs::write(&local_path, "local\n").await.unwrap();
+        source.url = Some(concat_string!("file://", local_path));
+        assert_eq!(download(&source).await.unwrap(), DownloadOutcome::Updated);
+        assert_eq!(
+            fs::read_to_string(&source.filepath).await.unwrap(),
+            "local\n"
+        );
+        assert_eq!(
+            download(&source).await.unwrap(),
+            DownloadOutcome::NotModified
+        );
+
+        // Without a published checksum the file is still used
+        source.verify = Some(SourceVerification::Md5);
+        source.url = Some(format!("http://{}/unverified", address));
+        assert_eq!(download(&source).await.unwrap(), DownloadOutcome::Updated);
+        assert_eq!(
+            fs::read_to_string(&source.filepath).await.unwrap(),
+            "unverified\n"
+        );
+
+        source.offline = true;
+        assert!(download(&source).await.is_err());
+
+        fs::remove_dir_all(directory).await.unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+// Geolocation of the addresses from MaxMind DB files (GeoLite2/GeoIP2 or DB-IP City and Country)
+//
+// Unlike the registry country from the RIR stats, which is where the holder is registered, the
+// geolocated country is where the address is actually used.
+
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use mtilib::types::GeoResponse;
+use tracing::{error, info};
+
+use crate::providers::{
+    self, LoadError, LookupKind, LookupValue, Provider, ProviderSource, ProviderStats,
+};
+
+pub struct GeoIpProvider {
+    pub readers: Vec<Reader<Vec<u8>>>,
+    pub sources: Vec<ProviderSource>,
+}
+
+impl GeoIpProvider {
+    /// Looks the address up in the databases in the order of the sources, the first match wins.
+    pub fn lookup(&self, address: IpAddr) -> Option<GeoResponse> {
+        // The IPv4 addresses live in the ::/96 subtree of the IPv6 databases
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+            address => address,
+        };
+
+        self.readers
+            .iter()
+            .find_map(|reader| match reader.lookup::<geoip2::City>(address) {
+                Ok(city) => Some(GeoResponse {
+                    country: city
+                        .country
+                        .and_then(|country| country.iso_code)
+                        .map(|iso_code| iso_code.to_string()),
+                    city: city
+                        .city
+                        .and_then(|city| city.names)
+                        .and_then(|names| names.get("en").map(|name| name.to_string())),
+                    latitude: city.location.as_ref().and_then(|x| x.latitude),
+                    longitude: city.location.as_ref().and_then(|x| x.longitude),
+                    accuracy_radius: city.location.as_ref().and_then(|x| x.accuracy_radius),
+                }),
+                Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+                Err(error) => {
+                    error!(
+                        "Failed to look up address {} in GeoIP database! ({})",
+                        address, error
+                    );
+                    None
+                }
+            })
+    }
+}
+
+impl Provider for GeoIpProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading GeoIP databases...");
+
+        let mut readers = Vec::new();
+        for source in sources.iter() {
+            let contents = providers::read_source_bytes(source).await?;
+
+            let reader = Reader::from_source(contents).map_err(|error| LoadError::Format {
+                filepath: source.filepath.clone(),
+                error: error.to_string(),
+            })?;
+            readers.push(reader);
+        }
+
+        info!("Loaded GeoIP databases!");
+
+        Ok(GeoIpProvider { readers, sources })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::Geolocation => self.lookup(address).map(LookupValue::Geolocation),
+            _ => None,
+        }
+    }
+
+    fn stats(&self) -> ProviderStats {
+        // The databases are searched in place, the nodes of the search trees are the closest thing to entries
+        ProviderStats {
+            entries: self
+                .readers
+                .iter()
+                .map(|reader| reader.metadata.node_count as usize)
+                .sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use super::GeoIpProvider;
+    use crate::providers::{Provider, ProviderSource};
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let provider = GeoIpProvider::load_sources(vec![ProviderSource::fixture("geoip.mmdb")])
+            .await
+            .unwrap();
+        assert_eq!(provider.readers.len(), 1);
+
+        let geo = provider
+            .lookup(IpAddr::from_str("1.1.1.1").unwrap())
+            .unwrap();
+        assert_eq!(geo.country.as_deref(), Some("AU"));
+        assert_eq!(geo.city.as_deref(), Some("Sydney"));
+        assert_eq!(geo.latitude, Some(-33.8688));
+        assert_eq!(geo.accuracy_radius, Some(100));
+
+        // Country level only
+        let geo = provider
+            .lookup(IpAddr::from_str("::ffff:2.2.2.2").unwrap())
+            .unwrap();
+        assert_eq!(geo.country.as_deref(), Some("FR"));
+        assert_eq!(geo.city, None);
+
+        let geo = provider
+            .lookup(IpAddr::from_str("2001:db8::1").unwrap())
+            .unwrap();
+        assert_eq!(geo.city.as_deref(), Some("Prague"));
+
+        assert!(provider
+            .lookup(IpAddr::from_str("8.8.8.8").unwrap())
+            .is_none());
+    }
+}
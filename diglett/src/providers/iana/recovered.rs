@@ -1,57 +1,134 @@
-use std::{fmt::Display, net::Ipv4Addr, path::Path, str::FromStr};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+};
 
-use config::Config;
 use mtilib::types::Rir;
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::providers::{self, CheckAndDownloadSource, ProviderSource};
+use crate::providers::{
+    self, CidrIndex, LoadError, LookupKind, LookupValue, Provider, ProviderSource, ProviderStats,
+    SkippedLines,
+};
 
+#[derive(Serialize, Deserialize)]
 pub struct RecoveredProvider {
     pub values: Vec<RecoveredEntry>,
+    pub index: CidrIndex,
+    #[serde(skip)]
     pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
 }
 
 impl RecoveredProvider {
-    pub async fn load(config: &Config) -> RecoveredProvider {
+    pub fn lookup(&self, address: IpAddr) -> Option<&RecoveredEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
+}
+
+// Start address, end address and the RIR the block was returned to, the rest of the columns is ignored
+fn parse_record(record: &csv::StringRecord) -> Result<RecoveredEntry, String> {
+    let field = |column| record.get(column).unwrap_or_default().trim();
+
+    Ok(RecoveredEntry {
+        start: Ipv4Addr::from_str(field(0)).map_err(|_| format!("bad address {}", field(0)))?,
+        end: Ipv4Addr::from_str(field(1)).map_err(|_| format!("bad address {}", field(1)))?,
+        rir: Rir::from_str(field(2)).map_err(|_| format!("unknown registry {}", field(2)))?,
+    })
+}
+
+impl Provider for RecoveredProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
         info!("Loading IANA recovered addresses...");
 
-        // Check if we need to redownload file
-        match providers::load_provider_sources(config, "iana.recovered") {
-            Some(sources) => {
-                sources.check_and_download().await;
-
-                let mut recovered_entries = Vec::new();
-                for source in sources.iter() {
-                    // Get the configured filepath and read the file into memory
-                    let source_filepath = Path::new(&source.filepath);
-
-                    let mut reader =
-                        csv::Reader::from_reader(std::fs::File::open(source_filepath).unwrap());
-
-                    // Parse
-                    for reader_line in reader.deserialize() {
-                        let entry: (String, String, String) = reader_line.unwrap();
-
-                        recovered_entries.push(RecoveredEntry {
-                            start: Ipv4Addr::from_str(&entry.0).unwrap(),
-                            end: Ipv4Addr::from_str(&entry.1).unwrap(),
-                            rir: Rir::from_str(&entry.2).unwrap(),
-                        });
-                    }
-                }
+        let mut recovered_entries = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            // Read the file into memory
+            let contents_str = providers::read_source(source).await?;
+            let mut reader = csv::Reader::from_reader(contents_str.as_bytes());
 
-                info!("Loaded IANA recovered addresses!");
+            // Parse
+            for record in reader.records() {
+                let result = match record {
+                    Ok(record) => parse_record(&record).map_err(|reason| {
+                        (record.position().map_or(0, |x| x.line() as usize), reason)
+                    }),
+                    Err(error) => Err((
+                        error.position().map_or(0, |x| x.line() as usize),
+                        error.to_string(),
+                    )),
+                };
 
-                RecoveredProvider {
-                    values: recovered_entries,
-                    sources,
+                match result {
+                    Ok(entry) => recovered_entries.push(entry),
+                    Err((line, reason)) => skipped.skip(source, line, reason),
                 }
             }
-            None => panic!("Failed to load sources for IANA recovered addresses!"),
+        }
+
+        info!("Loaded IANA recovered addresses!");
+
+        Ok(RecoveredProvider {
+            index: CidrIndex::from_ranges(
+                recovered_entries
+                    .iter()
+                    .map(|x| (IpAddr::V4(x.start), IpAddr::V4(x.end))),
+            ),
+            values: recovered_entries,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: RecoveredProvider = providers::snapshot::decode(data)?;
+        Some(RecoveredProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::Rir => self
+                .lookup(address)
+                .map(|entry| LookupValue::Rir(entry.rir.clone())),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| (IpAddr::V4(entry.start), IpAddr::V4(entry.end)))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct RecoveredEntry {
     pub start: Ipv4Addr,
     pub end: Ipv4Addr,
@@ -66,3 +143,39 @@ impl Display for RecoveredEntry {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use mtilib::types::Rir;
+
+    use super::RecoveredProvider;
+    use crate::providers::{Provider, ProviderSource};
+
+    #[tokio::test]
+    async fn test_load_malformed() {
+        let provider = RecoveredProvider::load_sources(vec![ProviderSource::fixture(
+            "ipv4-recovered-malformed.csv",
+        )])
+        .await
+        .unwrap();
+        assert_eq!(provider.values.len(), 2);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("51.1.2.3").unwrap())
+            .unwrap();
+        assert_eq!(entry.rir, Rir::Ripencc);
+
+        assert_eq!(provider.skipped.count, 3);
+        assert_eq!(
+            provider
+                .skipped
+                .lines
+                .iter()
+                .map(|x| x.line)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+    }
+}
@@ -1,57 +1,265 @@
-use std::{path::Path, str::FromStr};
+use std::{fmt::Display, net::IpAddr, str::FromStr};
 
-use config::Config;
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
+use mtilib::types::{AllocationState, SpecialPurposeResponse};
+
 use crate::{
-    providers::{self, CheckAndDownloadSource, ProviderSource},
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, Provider, ProviderSource,
+        ProviderStats, SkippedLines,
+    },
     utils::CIDR,
 };
 
+// The multicast blocks aren't in the special-purpose registries, IANA keeps them separately
+const MULTICAST_BLOCKS: [&str; 2] = ["224.0.0.0/4", "ff00::/8"];
+
+// Column positions in iana-ipv4-special-registry-1.csv and iana-ipv6-special-registry-1.csv
+const COLUMN_ADDRESS_BLOCK: usize = 0;
+const COLUMN_NAME: usize = 1;
+const COLUMN_RFC: usize = 2;
+const COLUMN_SOURCE: usize = 5;
+const COLUMN_DESTINATION: usize = 6;
+const COLUMN_FORWARDABLE: usize = 7;
+const COLUMN_GLOBALLY_REACHABLE: usize = 8;
+
+#[derive(Serialize, Deserialize)]
 pub struct ReservedProvider {
-    pub values: Vec<CIDR>,
+    pub values: Vec<ReservedEntry>,
+    pub index: CidrIndex,
+    pub multicast: Vec<CIDR>,
+    pub multicast_index: CidrIndex,
+    #[serde(skip)]
     pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
 }
 
 impl ReservedProvider {
-    pub async fn load(config: &Config) -> ReservedProvider {
-        info!("Loading IANA reserved addresses...");
+    pub fn lookup(&self, address: IpAddr) -> Option<&ReservedEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
 
-        // Check if we need to redownload file
-        match providers::load_provider_sources(config, "iana.reserved") {
-            Some(sources) => {
-                sources.check_and_download().await;
+    pub fn lookup_multicast(&self, address: IpAddr) -> Option<&CIDR> {
+        self.multicast_index
+            .lookup(address)
+            .map(|i| &self.multicast[i as usize])
+    }
+}
 
-                let mut reserved_blocks = Vec::new();
-                for source in sources.iter() {
-                    let source_filepath = Path::new(&source.filepath);
+// The registry uses footnotes ("True [1]") and N/A for the flags which don't apply
+fn parse_flag(field: &str) -> Option<bool> {
+    match field.split_whitespace().next() {
+        Some("True") => Some(true),
+        Some("False") => Some(false),
+        _ => None,
+    }
+}
 
-                    let mut reader =
-                        csv::Reader::from_reader(std::fs::File::open(source_filepath).unwrap());
+// The fields may span multiple lines and the names are quoted ("This network")
+fn parse_text(field: &str) -> String {
+    field
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches('"')
+        .to_string()
+}
 
-                    for reader_line in reader.deserialize() {
-                        let addresses: String = reader_line.unwrap();
+impl Provider for ReservedProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading IANA reserved addresses...");
 
-                        for address in addresses.split(",").map(|x| x.trim()) {
-                            let address = address.split(" ").collect::<Vec<_>>()[0];
-                            reserved_blocks.push(CIDR::from_str(address).unwrap());
-                        }
-                    }
-                }
+        let mut reserved_entries = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents_str = providers::read_source(source).await?;
+            let mut reader = csv::Reader::from_reader(contents_str.as_bytes());
 
-                // Manually add multicast block
-                // TODO: Move into its own section (create a new allocation state)
-                reserved_blocks.push(CIDR::from_str("224.0.0.0/4").unwrap());
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(error) => {
+                        let line = error.position().map_or(0, |x| x.line() as usize);
+                        skipped.skip(source, line, error);
+                        continue;
+                    }
+                };
+                let line = record.position().map_or(0, |x| x.line() as usize);
+                let field = |column| record.get(column).unwrap_or_default();
 
-                info!("Loaded IANA reserved addresses!");
-                reserved_blocks.sort();
+                // A single row can list multiple blocks ("192.0.0.170/32, 192.0.0.171/32")
+                for address in field(COLUMN_ADDRESS_BLOCK).split(",").map(|x| x.trim()) {
+                    let address = address.split(" ").collect::<Vec<_>>()[0];
+                    let cidr = match CIDR::from_str(address) {
+                        Ok(cidr) => cidr,
+                        Err(error) => {
+                            skipped.skip(source, line, format!("{} ({})", error, address));
+                            continue;
+                        }
+                    };
 
-                ReservedProvider {
-                    values: reserved_blocks,
-                    sources,
+                    reserved_entries.push(ReservedEntry {
+                        cidr,
+                        name: parse_text(field(COLUMN_NAME)),
+                        rfc: parse_text(field(COLUMN_RFC)),
+                        source: parse_flag(field(COLUMN_SOURCE)),
+                        destination: parse_flag(field(COLUMN_DESTINATION)),
+                        forwardable: parse_flag(field(COLUMN_FORWARDABLE)),
+                        globally_reachable: parse_flag(field(COLUMN_GLOBALLY_REACHABLE)),
+                    });
                 }
             }
-            None => panic!("Failed to load sources for IANA reserved addresses!"),
         }
+
+        let multicast_blocks = MULTICAST_BLOCKS
+            .iter()
+            .map(|block| CIDR::from_str(block).unwrap())
+            .collect::<Vec<_>>();
+
+        info!("Loaded IANA reserved addresses!");
+
+        Ok(ReservedProvider {
+            index: CidrIndex::build(reserved_entries.iter().map(|x| &x.cidr)),
+            values: reserved_entries,
+            multicast_index: CidrIndex::build(multicast_blocks.iter()),
+            multicast: multicast_blocks,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: ReservedProvider = providers::snapshot::decode(data)?;
+        Some(ReservedProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::AllocationState => match self.lookup_multicast(address) {
+                Some(_) => Some(LookupValue::AllocationState(AllocationState::Multicast)),
+                None => self
+                    .lookup(address)
+                    .map(|_| LookupValue::AllocationState(AllocationState::Reserved)),
+            },
+            LookupKind::SpecialPurpose => self
+                .lookup(address)
+                .map(|entry| LookupValue::SpecialPurpose(entry.clone().into())),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| &entry.cidr)
+                .chain(self.multicast.iter())
+                .map(|cidr| (cidr.first_address(), cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len() + self.multicast.len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReservedEntry {
+    pub cidr: CIDR,
+    pub name: String,
+    pub rfc: String,
+    pub source: Option<bool>,
+    pub destination: Option<bool>,
+    pub forwardable: Option<bool>,
+    pub globally_reachable: Option<bool>,
+}
+
+impl From<ReservedEntry> for SpecialPurposeResponse {
+    fn from(entry: ReservedEntry) -> Self {
+        SpecialPurposeResponse {
+            prefix: entry.cidr.to_string(),
+            name: entry.name,
+            rfc: entry.rfc,
+            source: entry.source,
+            destination: entry.destination,
+            forwardable: entry.forwardable,
+            globally_reachable: entry.globally_reachable,
+        }
+    }
+}
+
+impl Display for ReservedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "cidr: {}, name: {}, rfc: {}",
+            self.cidr, self.name, self.rfc
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use super::ReservedProvider;
+    use crate::providers::{Provider, ProviderSource};
+
+    #[tokio::test]
+    async fn test_load_special_registry() {
+        let provider = ReservedProvider::load_sources(vec![ProviderSource::fixture(
+            "iana-ipv4-special-registry.csv",
+        )])
+        .await
+        .unwrap();
+        assert_eq!(provider.values.len(), 7);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("10.1.2.3").unwrap())
+            .unwrap();
+        assert_eq!(entry.name, "Private-Use");
+        assert_eq!(entry.rfc, "[RFC1918]");
+        assert_eq!(entry.forwardable, Some(true));
+        assert_eq!(entry.globally_reachable, Some(false));
+
+        // The most specific block wins
+        let entry = provider
+            .lookup(IpAddr::from_str("0.0.0.0").unwrap())
+            .unwrap();
+        assert_eq!(entry.name, "This host on this network");
+
+        let entry = provider
+            .lookup(IpAddr::from_str("192.0.0.9").unwrap())
+            .unwrap();
+        assert_eq!(entry.globally_reachable, Some(true));
+
+        let entry = provider
+            .lookup(IpAddr::from_str("192.88.99.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.source, None);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("255.255.255.255").unwrap())
+            .unwrap();
+        assert_eq!(entry.rfc, "[RFC8190] [RFC919], Section 7");
     }
 }
@@ -0,0 +1,476 @@
+// Origin ASNs of the routed prefixes from MRT TABLE_DUMP_V2 RIB dumps (RFC 6396)
+//
+// Both the plain and the gzipped dumps (RIPE RIS) are supported, the bzip2 compressed RouteViews
+// dumps have to be decompressed first.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, PrefixFilter, Provider,
+        ProviderSource, ProviderStats, SkippedLines,
+    },
+    utils::CIDR,
+};
+
+const MRT_TABLE_DUMP_V2: u16 = 13;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+
+const ATTR_EXTENDED_LENGTH: u8 = 0x10;
+const ATTR_AS_PATH: u8 = 2;
+const AS_SEQUENCE: u8 = 2;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const HEADER_LENGTH: usize = 12;
+
+// Bounds checked big endian reader over a record
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(count)?)?;
+        self.pos += count;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|x| x[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|x| u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+// The origin is the last AS of the path, paths ending with an AS_SET (aggregates) don't have a single origin
+fn origin_asn(attributes: &[u8]) -> Option<Option<u32>> {
+    let mut reader = Reader::new(attributes);
+
+    while !reader.is_empty() {
+        let flags = reader.u8()?;
+        let kind = reader.u8()?;
+        let length = match flags & ATTR_EXTENDED_LENGTH {
+            0 => reader.u8()? as usize,
+            _ => reader.u16()? as usize,
+        };
+        let value = reader.bytes(length)?;
+
+        if kind != ATTR_AS_PATH {
+            continue;
+        }
+
+        // The AS numbers are always encoded with 4 bytes in TABLE_DUMP_V2
+        let mut path = Reader::new(value);
+        let mut origin = None;
+        while !path.is_empty() {
+            let segment_type = path.u8()?;
+            let segment_length = path.u8()? as usize;
+            let asns = path.bytes(segment_length * 4)?;
+
+            origin = match (segment_type, asns.len()) {
+                (AS_SEQUENCE, 4..) => Some(u32::from_be_bytes(
+                    asns[asns.len() - 4..].try_into().unwrap(),
+                )),
+                _ => None,
+            };
+        }
+
+        return Some(origin);
+    }
+
+    Some(None)
+}
+
+// Parses a RIB_IPV4_UNICAST or RIB_IPV6_UNICAST record, returning the prefix and the origin seen by each peer
+fn parse_rib(body: &[u8], ipv6: bool, addpath: bool) -> Option<(CIDR, Vec<u32>)> {
+    let mut reader = Reader::new(body);
+
+    let _sequence = reader.u32()?;
+    let mask = reader.u8()?;
+    let prefix_bytes = reader.bytes(mask.div_ceil(8) as usize)?;
+    let prefix = match ipv6 {
+        false if mask <= 32 => {
+            let mut octets = [0; 4];
+            octets[..prefix_bytes.len()].copy_from_slice(prefix_bytes);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        true if mask <= 128 => {
+            let mut octets = [0; 16];
+            octets[..prefix_bytes.len()].copy_from_slice(prefix_bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    let entry_count = reader.u16()?;
+    let mut origins = Vec::new();
+    for _ in 0..entry_count {
+        let _peer_index = reader.u16()?;
+        let _originated_time = reader.u32()?;
+        if addpath {
+            let _path_id = reader.u32()?;
+        }
+        let attributes_length = reader.u16()? as usize;
+
+        if let Some(origin) = origin_asn(reader.bytes(attributes_length)?)? {
+            origins.push(origin);
+        }
+    }
+
+    Some((CIDR::new(prefix, mask as u16), origins))
+}
+
+// Fills the buffer unless the data ends first, returns how much of it was filled
+fn read_full(data: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match data.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(count) => filled += count,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+/// Parses the RIB records of the dump one at a time, the malformed ones are skipped at their byte
+/// offsets (of the decompressed data) and a truncated record ends the dump.
+pub fn parse_dump(
+    mut data: impl Read,
+    source: &ProviderSource,
+    skipped: &mut SkippedLines,
+) -> io::Result<Vec<RibEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    let mut header = [0; HEADER_LENGTH];
+    let mut body = Vec::new();
+
+    loop {
+        match read_full(&mut data, &mut header)? {
+            0 => break,
+            HEADER_LENGTH => {}
+            _ => {
+                skipped.skip_record(source, offset, "truncated record");
+                break;
+            }
+        }
+        let mut reader = Reader::new(&header);
+        let _timestamp = reader.u32();
+        let kind = reader.u16().unwrap();
+        let subtype = reader.u16().unwrap();
+        let length = reader.u32().unwrap() as usize;
+
+        // Grown as it's read, so that a bogus length can't allocate more than there is
+        body.clear();
+        data.by_ref().take(length as u64).read_to_end(&mut body)?;
+        if body.len() < length {
+            skipped.skip_record(source, offset, "truncated record");
+            break;
+        }
+        let record_offset = offset;
+        offset += HEADER_LENGTH + length;
+
+        // The peer index table isn't needed, other record types (BGP4MP...) are skipped
+        if kind != MRT_TABLE_DUMP_V2 {
+            continue;
+        }
+        let rib = match subtype {
+            RIB_IPV4_UNICAST => parse_rib(&body, false, false),
+            RIB_IPV6_UNICAST => parse_rib(&body, true, false),
+            RIB_IPV4_UNICAST_ADDPATH => parse_rib(&body, false, true),
+            RIB_IPV6_UNICAST_ADDPATH => parse_rib(&body, true, true),
+            _ => continue,
+        };
+
+        match rib {
+            Some((cidr, origins)) => entries.push(RibEntry::new(cidr, origins)),
+            None => skipped.skip_record(source, record_offset, "malformed RIB record"),
+        }
+    }
+
+    Ok(entries)
+}
+
+// The dumps are several GB once decompressed, so they're parsed as they're read instead of being
+// read into memory first. Loads run on a blocking thread, so the reads can block.
+fn load_dump(
+    source: &ProviderSource,
+    skipped: &mut SkippedLines,
+) -> Result<Vec<RibEntry>, LoadError> {
+    let file = File::open(&source.filepath).map_err(|error| LoadError::Io {
+        filepath: source.filepath.clone(),
+        error,
+    })?;
+    let mut reader = BufReader::new(file);
+
+    let gzipped = reader
+        .fill_buf()
+        .map(|buffer| buffer.starts_with(&GZIP_MAGIC));
+    let entries = match gzipped {
+        Ok(true) => parse_dump(GzDecoder::new(reader), source, skipped),
+        Ok(false) => parse_dump(reader, source, skipped),
+        Err(error) => Err(error),
+    };
+
+    entries.map_err(|error| LoadError::Format {
+        filepath: source.filepath.clone(),
+        error: error.to_string(),
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RibEntry {
+    pub cidr: CIDR,
+    /// Origin ASNs with the number of peers which have seen them, the most seen first
+    pub origins: Vec<(u32, u32)>,
+}
+
+impl RibEntry {
+    pub fn new(cidr: CIDR, peer_origins: Vec<u32>) -> Self {
+        let mut counts: HashMap<u32, u32> = HashMap::new();
+        for origin in peer_origins {
+            *counts.entry(origin).or_default() += 1;
+        }
+
+        let mut origins = counts.into_iter().collect::<Vec<_>>();
+        origins.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        RibEntry { cidr, origins }
+    }
+
+    /// Multiple origin AS conflict
+    pub fn is_moas(&self) -> bool {
+        self.origins.len() > 1
+    }
+}
+
+impl Display for RibEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "cidr: {}, origins: {:?}",
+            self.cidr,
+            self.origins.iter().map(|x| x.0).collect::<Vec<_>>()
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RibProvider {
+    pub values: Vec<RibEntry>,
+    pub index: CidrIndex,
+    pub skipped: SkippedLines,
+    #[serde(skip)]
+    pub sources: Vec<ProviderSource>,
+}
+
+impl RibProvider {
+    pub fn lookup(&self, address: IpAddr) -> Option<&RibEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
+}
+
+impl Provider for RibProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading MRT RIB dumps...");
+
+        let mut rib_entries = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            rib_entries.append(&mut load_dump(source, &mut skipped)?);
+        }
+
+        info!("Loaded MRT RIB dumps!");
+
+        Ok(RibProvider {
+            index: CidrIndex::build(rib_entries.iter().map(|x| &x.cidr)),
+            values: rib_entries,
+            skipped,
+            sources,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: RibProvider = providers::snapshot::decode(data)?;
+        Some(RibProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        let entry = self.lookup(address)?;
+
+        match kind {
+            LookupKind::Asn => entry.origins.first().map(|x| LookupValue::Asn(x.0)),
+            LookupKind::Routed => Some(LookupValue::Route {
+                prefix: entry.cidr.to_string(),
+                origins: entry.origins.iter().map(|x| x.0).collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    // Same as the ASN lookups, the prefixes count for their most seen origin
+    fn prefixes(&self, filter: &PrefixFilter) -> Option<Vec<CIDR>> {
+        match filter {
+            PrefixFilter::Asn(asn) => Some(
+                self.values
+                    .iter()
+                    .filter(|entry| entry.origins.first().is_some_and(|x| x.0 == *asn))
+                    .map(|entry| entry.cidr)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| (entry.cidr.first_address(), entry.cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use super::{parse_dump, RibProvider};
+    use crate::providers::{Provider, ProviderSource, SkippedLines};
+
+    async fn load(fixture: &str) -> RibProvider {
+        RibProvider::load_sources(vec![ProviderSource::fixture(fixture)])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_load_rib() {
+        let provider = load("rib.mrt").await;
+        assert_eq!(provider.values.len(), 5);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("1.1.1.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.origins, vec![(13335, 2)]);
+        assert!(!entry.is_moas());
+
+        let entry = provider
+            .lookup(IpAddr::from_str("192.0.2.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.origins, vec![(64496, 2), (64497, 1)]);
+        assert!(entry.is_moas());
+
+        // Aggregates ending with an AS_SET are routed without a known origin
+        let entry = provider
+            .lookup(IpAddr::from_str("203.0.113.1").unwrap())
+            .unwrap();
+        assert!(entry.origins.is_empty());
+
+        let entry = provider
+            .lookup(IpAddr::from_str("198.19.0.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.origins, vec![(64498, 1)]);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("2001:db8::1").unwrap())
+            .unwrap();
+        assert_eq!(entry.cidr.to_string(), "2001:db8::/32");
+        assert_eq!(entry.origins, vec![(64499, 1)]);
+
+        assert!(provider
+            .lookup(IpAddr::from_str("8.8.8.8").unwrap())
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_gzipped_rib() {
+        assert_eq!(load("rib.mrt.gz").await.values.len(), 5);
+    }
+
+    #[test]
+    fn test_skipped_records() {
+        let source = ProviderSource::fixture("rib.mrt");
+        let data = std::fs::read(&source.filepath).unwrap();
+
+        // Offsets of the records, from the lengths in their headers
+        let mut offsets = vec![0];
+        while let Some(offset) = offsets
+            .last()
+            .copied()
+            .filter(|offset| *offset < data.len())
+        {
+            let length = u32::from_be_bytes(data[offset + 8..offset + 12].try_into().unwrap());
+            offsets.push(offset + 12 + length as usize);
+        }
+        let last = offsets[offsets.len() - 2];
+
+        // The cut short last record ends the dump, the ones before it are kept
+        let mut skipped = SkippedLines::default();
+        let entries = parse_dump(&data[..data.len() - 1], &source, &mut skipped).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(skipped.count, 1);
+        assert_eq!(skipped.lines[0].line, last);
+        assert_eq!(skipped.lines[0].reason, "truncated record");
+
+        // A RIB record with an impossible prefix length is skipped, the dump goes on
+        let mut malformed = data.clone();
+        malformed[last + 16] = 255;
+        let mut skipped = SkippedLines::default();
+        let entries = parse_dump(malformed.as_slice(), &source, &mut skipped).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(skipped.lines[0].line, last);
+        assert_eq!(skipped.lines[0].reason, "malformed RIB record");
+    }
+}
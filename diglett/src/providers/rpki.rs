@@ -0,0 +1,240 @@
+// Validated ROA payloads (VRPs) from the JSON exports of the relying party software
+//
+// Both the Routinator (`"asn": "AS13335"`) and the rpki-client (`"asn": 13335`) flavours of the
+// `{"roas": [...]}` export are supported, the routes are validated as described in RFC 6811.
+
+use std::{collections::HashMap, fmt::Display, net::IpAddr, str::FromStr};
+
+use mtilib::types::RpkiState;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    providers::{
+        self, LoadError, LookupKind, LookupValue, Provider, ProviderSource, ProviderStats,
+        SkippedLines,
+    },
+    utils::CIDR,
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RoaAsn {
+    Number(u32),
+    Text(String),
+}
+
+impl RoaAsn {
+    fn parse(&self) -> Option<u32> {
+        match self {
+            RoaAsn::Number(asn) => Some(*asn),
+            RoaAsn::Text(asn) => asn
+                .strip_prefix("AS")
+                .or(asn.strip_prefix("as"))
+                .unwrap_or(asn)
+                .parse()
+                .ok(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RoaRecord {
+    asn: RoaAsn,
+    prefix: String,
+    #[serde(rename = "maxLength")]
+    max_length: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct RoaExport {
+    roas: Vec<RoaRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Roa {
+    pub cidr: CIDR,
+    pub max_length: u16,
+    pub asn: u32,
+}
+
+impl Display for Roa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "cidr: {}, max_length: {}, asn: {}",
+            self.cidr, self.max_length, self.asn
+        ))
+    }
+}
+
+// The prefixes are keyed by their first address, so that the host bits don't matter
+fn network(prefix: IpAddr, mask: u16) -> CIDR {
+    CIDR::new(CIDR::new(prefix, mask).first_address(), mask)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpkiProvider {
+    pub values: Vec<Roa>,
+    pub index: HashMap<CIDR, Vec<usize>>,
+    #[serde(skip)]
+    pub sources: Vec<ProviderSource>,
+    /// The malformed ROAs are numbered by their position in the export instead of their line
+    pub skipped: SkippedLines,
+}
+
+impl RpkiProvider {
+    pub fn build_index(values: &[Roa]) -> HashMap<CIDR, Vec<usize>> {
+        let mut index: HashMap<CIDR, Vec<usize>> = HashMap::new();
+        for (i, roa) in values.iter().enumerate() {
+            index
+                .entry(network(roa.cidr.prefix, roa.cidr.mask))
+                .or_default()
+                .push(i);
+        }
+        index
+    }
+
+    /// ROAs covering the prefix, from the least to the most specific.
+    pub fn covering(&self, prefix: &CIDR) -> Vec<&Roa> {
+        (0..=prefix.mask)
+            .filter_map(|mask| self.index.get(&network(prefix.prefix, mask)))
+            .flatten()
+            .map(|i| &self.values[*i])
+            .collect()
+    }
+
+    /// Origin validation of a route, routes without a single origin (AS_SET) can't be valid.
+    pub fn validate(&self, prefix: &CIDR, origin: Option<u32>) -> RpkiState {
+        let covering = self.covering(prefix);
+        if covering.is_empty() {
+            return RpkiState::NotFound;
+        }
+
+        // AS0 ROAs never match any route (RFC 7607)
+        let matched = covering.iter().any(|roa| {
+            origin.is_some_and(|origin| roa.asn == origin && roa.asn != 0)
+                && prefix.mask <= roa.max_length
+        });
+        match matched {
+            true => RpkiState::Valid,
+            false => RpkiState::Invalid,
+        }
+    }
+}
+
+impl Provider for RpkiProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading RPKI ROAs...");
+
+        let mut roas = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents = providers::read_source(source).await?;
+
+            let export = serde_json::from_str::<RoaExport>(&contents).map_err(|error| {
+                LoadError::Format {
+                    filepath: source.filepath.clone(),
+                    error: error.to_string(),
+                }
+            })?;
+
+            for (i, record) in export.roas.into_iter().enumerate() {
+                let (Ok(cidr), Some(asn)) = (CIDR::from_str(&record.prefix), record.asn.parse())
+                else {
+                    skipped.skip(
+                        source,
+                        i + 1,
+                        format!("malformed ROA for {}", record.prefix),
+                    );
+                    continue;
+                };
+
+                roas.push(Roa {
+                    max_length: record.max_length.unwrap_or(cidr.mask),
+                    cidr,
+                    asn,
+                });
+            }
+        }
+
+        info!("Loaded RPKI ROAs!");
+
+        Ok(RpkiProvider {
+            index: RpkiProvider::build_index(&roas),
+            values: roas,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: RpkiProvider = providers::snapshot::decode(data)?;
+        Some(RpkiProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, _kind: LookupKind, _address: IpAddr) -> Option<LookupValue> {
+        None
+    }
+
+    fn validate(&self, prefix: &CIDR, origin: Option<u32>) -> Option<RpkiState> {
+        Some(self.validate(prefix, origin))
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use mtilib::types::RpkiState;
+
+    use super::RpkiProvider;
+    use crate::{
+        providers::{Provider, ProviderSource},
+        utils::CIDR,
+    };
+
+    #[tokio::test]
+    async fn test_validate() {
+        let provider = RpkiProvider::load_sources(vec![ProviderSource::fixture("roas.json")])
+            .await
+            .unwrap();
+        assert_eq!(provider.values.len(), 4);
+
+        let validate =
+            |prefix: &str, origin| provider.validate(&CIDR::from_str(prefix).unwrap(), origin);
+
+        assert_eq!(validate("1.1.1.0/24", Some(13335)), RpkiState::Valid);
+        assert_eq!(validate("2001:db8::/48", Some(64499)), RpkiState::Valid);
+        // Wrong origin
+        assert_eq!(validate("1.1.1.0/24", Some(64496)), RpkiState::Invalid);
+        // More specific than the max length
+        assert_eq!(validate("192.0.2.0/25", Some(64496)), RpkiState::Invalid);
+        assert_eq!(validate("192.0.2.0/24", Some(64496)), RpkiState::Valid);
+        // No single origin
+        assert_eq!(validate("1.1.1.0/24", None), RpkiState::Invalid);
+        // AS0
+        assert_eq!(validate("198.51.100.0/24", Some(0)), RpkiState::Invalid);
+        assert_eq!(validate("8.8.8.0/24", Some(15169)), RpkiState::NotFound);
+    }
+}
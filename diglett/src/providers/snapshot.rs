@@ -0,0 +1,255 @@
+// Binary snapshot of the parsed providers, so that a restart doesn't have to parse the sources again
+//
+// The file starts with a magic, the format version and a CRC32 of the rest, which is the bincode of
+// the providers together with the fingerprints (size and modification time) of their source files.
+// On start the snapshot is memory-mapped and a provider is restored from it only when its sources
+// haven't changed since, otherwise the provider is parsed from the sources as usual.
+
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use memmap2::Mmap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::error;
+
+use crate::providers::ProviderSource;
+
+const MAGIC: [u8; 8] = *b"DIGLSNAP";
+// Bumped on every change of the layout of the file, the layout of the providers is tied to the release
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Magic,
+    Version { expected: String, actual: String },
+    Checksum { expected: u32, actual: u32 },
+    Encoding(bincode::Error),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "io error: {}", error),
+            SnapshotError::Magic => write!(f, "not a snapshot"),
+            SnapshotError::Version { expected, actual } => {
+                write!(
+                    f,
+                    "version mismatch (expected {}, got {})",
+                    expected, actual
+                )
+            }
+            SnapshotError::Checksum { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {:08x}, got {:08x})",
+                    expected, actual
+                )
+            }
+            SnapshotError::Encoding(error) => write!(f, "encoding error: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(error: bincode::Error) -> Self {
+        SnapshotError::Encoding(error)
+    }
+}
+
+/// What a source file looked like when it was parsed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFingerprint {
+    pub filepath: String,
+    pub length: u64,
+    /// Nanoseconds since the unix epoch
+    pub modified: u128,
+}
+
+/// Fingerprints of all of the sources, `None` when any of them is missing.
+pub fn fingerprints(sources: &[ProviderSource]) -> Option<Vec<SourceFingerprint>> {
+    sources
+        .iter()
+        .map(|source| {
+            let metadata = fs::metadata(&source.filepath).ok()?;
+            Some(SourceFingerprint {
+                filepath: source.filepath.clone(),
+                length: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()?
+                    .duration_since(UNIX_EPOCH)
+                    .ok()?
+                    .as_nanos(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderSnapshot<'a> {
+    pub id: String,
+    /// Type of the provider, the same data can't be restored into a different implementation
+    pub kind: String,
+    pub sources: Vec<SourceFingerprint>,
+    #[serde(borrow)]
+    pub data: &'a [u8],
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotContents<'a> {
+    diglett_version: String,
+    #[serde(borrow)]
+    providers: Vec<ProviderSnapshot<'a>>,
+}
+
+/// Serializes the parsed data of a provider.
+pub fn encode<T: Serialize>(value: &T) -> Option<Vec<u8>> {
+    bincode::serialize(value)
+        .inspect_err(|error| error!("Failed to encode provider snapshot! ({})", error))
+        .ok()
+}
+
+/// Deserializes the parsed data of a provider, `None` when it doesn't match the provider.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    bincode::deserialize(data)
+        .inspect_err(|error| error!("Failed to decode provider snapshot! ({})", error))
+        .ok()
+}
+
+/// Memory-mapped snapshot with a checked header.
+pub struct Snapshot {
+    mmap: Mmap,
+}
+
+impl Snapshot {
+    pub fn open(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let file = File::open(path)?;
+        // The snapshot is only ever replaced by a rename, so the mapped file doesn't change underneath
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LENGTH || mmap[..8] != MAGIC {
+            return Err(SnapshotError::Magic);
+        }
+
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(SnapshotError::Version {
+                expected: FORMAT_VERSION.to_string(),
+                actual: version.to_string(),
+            });
+        }
+
+        let expected = u32::from_le_bytes(mmap[12..16].try_into().unwrap());
+        let actual = crc32fast::hash(&mmap[HEADER_LENGTH..]);
+        if expected != actual {
+            return Err(SnapshotError::Checksum { expected, actual });
+        }
+
+        Ok(Snapshot { mmap })
+    }
+
+    /// The snapshots of the providers, borrowing their data from the mapped file.
+    pub fn providers(&self) -> Result<Vec<ProviderSnapshot<'_>>, SnapshotError> {
+        let contents: SnapshotContents = bincode::deserialize(&self.mmap[HEADER_LENGTH..])?;
+
+        if contents.diglett_version != env!("CARGO_PKG_VERSION") {
+            return Err(SnapshotError::Version {
+                expected: env!("CARGO_PKG_VERSION").to_string(),
+                actual: contents.diglett_version,
+            });
+        }
+
+        Ok(contents.providers)
+    }
+}
+
+pub fn write(path: &Path, providers: Vec<ProviderSnapshot>) -> Result<(), SnapshotError> {
+    let body = bincode::serialize(&SnapshotContents {
+        diglett_version: env!("CARGO_PKG_VERSION").to_string(),
+        providers,
+    })?;
+
+    if let Some(prefix) = path.parent() {
+        fs::create_dir_all(prefix)?;
+    }
+
+    // Same as with the downloads, the file is swapped in only once it's complete
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(&MAGIC)?;
+    file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    file.write_all(&crc32fast::hash(&body).to_le_bytes())?;
+    file.write_all(&body)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{write, ProviderSnapshot, Snapshot, SnapshotError, SourceFingerprint};
+
+    #[test]
+    fn test_snapshot() {
+        let directory =
+            std::env::temp_dir().join(format!("diglett-snapshot-{}", std::process::id()));
+        let path = directory.join("snapshot.bin");
+
+        let fingerprint = SourceFingerprint {
+            filepath: String::from("./data/arin/stats/arin.txt"),
+            length: 42,
+            modified: 1_700_000_000_000_000_000,
+        };
+        write(
+            &path,
+            vec![ProviderSnapshot {
+                id: String::from("arin.stats"),
+                kind: String::from("arin.stats"),
+                sources: vec![fingerprint.clone()],
+                data: b"parsed",
+            }],
+        )
+        .unwrap();
+
+        let snapshot = Snapshot::open(&path).unwrap();
+        let providers = snapshot.providers().unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].id, "arin.stats");
+        assert_eq!(providers[0].sources, vec![fingerprint]);
+        assert_eq!(providers[0].data, b"parsed");
+        drop(snapshot);
+
+        // A single flipped bit is caught by the checksum
+        let mut contents = fs::read(&path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&path, contents).unwrap();
+        assert!(matches!(
+            Snapshot::open(&path),
+            Err(SnapshotError::Checksum { .. })
+        ));
+
+        fs::write(&path, b"not a snapshot").unwrap();
+        assert!(matches!(Snapshot::open(&path), Err(SnapshotError::Magic)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,25 +1,33 @@
-use std::{fmt::Display, net::Ipv4Addr, path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
-use config::Config;
-use mtilib::types::{AllocationState, Rir};
-use tokio::{fs::File, io::AsyncReadExt};
+use mtilib::types::{AllocationState, AsnBlock, AsnResponse, HolderResponse, PrefixBlock, Rir};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
-    providers::{self, CheckAndDownloadSource},
-    utils::CIDR,
+    index::PrefixIndex,
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, PrefixFilter, Provider, ProviderStats,
+        SkippedLines,
+    },
+    utils::{self, CIDR},
 };
 
 use super::ProviderSource;
 
-const HEADER_SIZE: u32 = 4;
-
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StatsEntry {
     pub cidr: CIDR,
     pub allocation_state: AllocationState,
     pub rir: Rir,
     pub country: Option<String>,
+    pub registration_date: Option<String>,
+    pub opaque_id: Option<String>,
 }
 
 impl PartialOrd for StatsEntry {
@@ -46,110 +54,525 @@ impl Display for StatsEntry {
     }
 }
 
+/// A block of consecutive AS numbers from the `asn` section of the stats files.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AsnEntry {
+    pub start: u32,
+    pub count: u32,
+    pub allocation_state: AllocationState,
+    pub rir: Rir,
+    pub country: Option<String>,
+    pub registration_date: Option<String>,
+    pub opaque_id: Option<String>,
+}
+
+impl AsnEntry {
+    pub fn end(&self) -> u32 {
+        self.start.saturating_add(self.count.saturating_sub(1))
+    }
+}
+
+impl Display for AsnEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "asns: {}-{}, allocation_state: {}, rir: {}, country: {}",
+            self.start,
+            self.end(),
+            self.allocation_state,
+            self.rir,
+            self.country.as_ref().unwrap_or(&String::from("-"))
+        ))
+    }
+}
+
+/// Positions of the records registered to the same holder.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Holder {
+    pub values: Vec<u32>,
+    pub asns: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct StatsProvider {
     pub values: Vec<StatsEntry>,
+    pub index: CidrIndex,
+    pub asns: Vec<AsnEntry>,
+    pub asn_index: PrefixIndex<u32, u32>,
+    pub holders: HashMap<String, Holder>,
+    #[serde(skip)]
     pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
+}
+
+enum StatsRecord {
+    /// A block of addresses, split into prefixes when its size isn't a power of two
+    Addresses(Vec<StatsEntry>),
+    Asn(AsnEntry),
+}
+
+// Converts the YYYYMMDD dates used by the stats files into YYYY-MM-DD, empty or zero dates are missing
+fn parse_date(date: &str) -> Option<String> {
+    if date.len() != 8 || !date.bytes().all(|x| x.is_ascii_digit()) || date == "00000000" {
+        return None;
+    }
+
+    Some(format!("{}-{}-{}", &date[0..4], &date[4..6], &date[6..8]))
+}
+
+// The opaque id is the first extension field, it's missing in the non-extended files and empty for available records
+fn parse_opaque_id(parts: &[&str]) -> Option<String> {
+    parts
+        .get(7)
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+}
+
+// registry|cc|type|start|value|date|status[|extensions...], `None` for the records of the other types
+fn parse_record(parts: &[&str]) -> Result<Option<StatsRecord>, String> {
+    let parse_number = |field: &str| {
+        field
+            .parse::<u32>()
+            .map_err(|_| format!("bad number {}", field))
+    };
+
+    let cidrs = match parts[2] {
+        // IPv4 records contain the address count, which doesn't have to be a power of two
+        "ipv4" => {
+            let prefix =
+                Ipv4Addr::from_str(parts[3]).map_err(|_| format!("bad address {}", parts[3]))?;
+            let count = parse_number(parts[4])?;
+            if count == 0 {
+                return Err(String::from("empty address block"));
+            }
+            let end = prefix
+                .to_bits()
+                .checked_add(count - 1)
+                .ok_or_else(|| format!("address block {}+{} out of range", parts[3], count))?;
+
+            Some(utils::range_prefixes(
+                IpAddr::V4(prefix),
+                IpAddr::V4(Ipv4Addr::from_bits(end)),
+            ))
+        }
+        // IPv6 records contain the prefix length instead of the address count
+        "ipv6" => {
+            let prefix =
+                Ipv6Addr::from_str(parts[3]).map_err(|_| format!("bad address {}", parts[3]))?;
+            let mask = parts[4]
+                .parse::<u16>()
+                .ok()
+                .filter(|mask| *mask <= 128)
+                .ok_or_else(|| format!("bad prefix length {}", parts[4]))?;
+
+            Some(vec![CIDR {
+                prefix: IpAddr::V6(prefix),
+                mask,
+            }])
+        }
+        "asn" => None,
+        _ => return Ok(None),
+    };
+
+    let rir = Rir::from_str(parts[0]).map_err(|_| format!("unknown registry {}", parts[0]))?;
+    let allocation_state =
+        AllocationState::from_str(parts[6]).map_err(|_| format!("unknown status {}", parts[6]))?;
+    let country = match allocation_state {
+        AllocationState::Allocated => Some(parts[1].to_string()),
+        _ => None,
+    };
+
+    Ok(Some(match cidrs {
+        Some(cidrs) => StatsRecord::Addresses(
+            cidrs
+                .into_iter()
+                .map(|cidr| StatsEntry {
+                    cidr,
+                    allocation_state: allocation_state.clone(),
+                    rir: rir.clone(),
+                    country: country.clone(),
+                    registration_date: parse_date(parts[5]),
+                    opaque_id: parse_opaque_id(parts),
+                })
+                .collect(),
+        ),
+        None => StatsRecord::Asn(AsnEntry {
+            start: parse_number(parts[3])?,
+            count: parse_number(parts[4])?,
+            allocation_state,
+            rir,
+            country,
+            registration_date: parse_date(parts[5]),
+            opaque_id: parse_opaque_id(parts),
+        }),
+    }))
 }
 
 impl StatsProvider {
-    async fn load_source(source: &ProviderSource) -> Vec<StatsEntry> {
-        let source_filepath = Path::new(&source.filepath);
+    pub fn lookup(&self, address: IpAddr) -> Option<&StatsEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
 
-        let mut file = File::open(source_filepath).await.unwrap();
-        let mut contents_str = String::new();
-        file.read_to_string(&mut contents_str).await.unwrap();
-        let lines = contents_str.split("\n").collect::<Vec<_>>();
+    pub fn lookup_asn(&self, asn: u32) -> Option<&AsnEntry> {
+        self.asn_index.lookup(asn).map(|i| &self.asns[i as usize])
+    }
+
+    /// Returns all of the address blocks and ASNs registered to the holder with the opaque id.
+    pub fn lookup_holder(&self, opaque_id: &str) -> Option<(Vec<&StatsEntry>, Vec<&AsnEntry>)> {
+        self.holders.get(opaque_id).map(|holder| {
+            (
+                holder
+                    .values
+                    .iter()
+                    .map(|i| &self.values[*i as usize])
+                    .collect(),
+                holder
+                    .asns
+                    .iter()
+                    .map(|i| &self.asns[*i as usize])
+                    .collect(),
+            )
+        })
+    }
+
+    async fn load_source(
+        source: &ProviderSource,
+        skipped: &mut SkippedLines,
+    ) -> Result<(Vec<StatsEntry>, Vec<AsnEntry>), LoadError> {
+        let contents_str = providers::read_source(source).await?;
 
         let mut stat_entries = Vec::new();
+        let mut asn_entries = Vec::new();
 
-        let mut i: usize = 0;
-        let mut parsed_header = false;
-        let mut parsed_header_sections: u32 = 0;
-        let mut ipv4_offset: u32 = 0;
-        let mut ipv4_count: u32 = 0;
-        let mut parsed_ipv4: u32 = 0;
-        loop {
-            if i > lines.len() {
-                return stat_entries;
+        for (i, line) in contents_str.lines().enumerate() {
+            if line.starts_with("#") {
+                continue;
             }
 
-            if lines[i].starts_with("#") {
-                i += 1;
+            // The version header and the summary lines have fewer fields than the records
+            let parts = line.split("|").collect::<Vec<_>>();
+            if parts.len() < 7 {
                 continue;
             }
 
-            if parsed_header {
-                if parsed_ipv4 >= ipv4_count {
-                    return stat_entries;
-                }
-
-                let parts = lines[i].split("|").collect::<Vec<_>>();
-                let alloc_state = AllocationState::from_str(parts[6]).unwrap();
-                let country = match alloc_state {
-                    AllocationState::Allocated => Some(parts[1].to_string()),
-                    _ => None,
-                };
-
-                stat_entries.push(StatsEntry {
-                    cidr: CIDR {
-                        prefix: Ipv4Addr::from_str(parts[3]).unwrap().into(),
-                        mask: (u32::MAX & !(parts[4].parse::<u32>().unwrap() - 1)).count_ones()
-                            as u16,
-                    },
-                    allocation_state: alloc_state,
-                    rir: Rir::from_str(parts[0]).unwrap(),
-                    country,
-                });
-
-                parsed_ipv4 += 1;
-                i += 1;
-            } else {
-                let parts = lines[i].split("|").collect::<Vec<_>>();
-
-                match parts[2] {
-                    "ipv4" => {
-                        parsed_header_sections += 1;
-                        parsed_header = true;
-                        ipv4_count = parts[4].parse::<u32>().unwrap();
-                        i += (ipv4_offset + (HEADER_SIZE - parsed_header_sections) + 1) as usize;
-                        // header size is always 4
-                    }
-                    "ipv6" | "asn" => {
-                        parsed_header_sections += 1;
-                        ipv4_offset += parts[4].parse::<u32>().unwrap();
-                        i += 1;
-                    }
-                    _ => {
-                        parsed_header_sections += 1;
-                        i += 1;
-                    }
-                }
+            match parse_record(&parts) {
+                Ok(Some(StatsRecord::Addresses(mut entries))) => stat_entries.append(&mut entries),
+                Ok(Some(StatsRecord::Asn(entry))) => asn_entries.push(entry),
+                Ok(None) => {}
+                Err(reason) => skipped.skip(source, i + 1, reason),
             }
         }
+
+        Ok((stat_entries, asn_entries))
     }
+}
 
-    pub async fn load(config: &Config) -> Self {
+impl Provider for StatsProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
         info!("Loading ARIN stats...");
 
-        // Check if we need to redownload file
-        match providers::load_provider_sources(config, "arin.stats") {
-            Some(sources) => {
-                sources.check_and_download().await;
+        let mut stat_entries = Vec::new();
+        let mut asn_entries = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let (mut source_stats, mut source_asns) =
+                StatsProvider::load_source(source, &mut skipped).await?;
+            stat_entries.append(&mut source_stats);
+            asn_entries.append(&mut source_asns);
+        }
 
-                let mut stat_entries = Vec::new();
-                for source in sources.iter() {
-                    stat_entries.append(&mut StatsProvider::load_source(source).await);
-                }
+        let mut holders: HashMap<String, Holder> = HashMap::new();
+        for (i, entry) in stat_entries.iter().enumerate() {
+            if let Some(opaque_id) = &entry.opaque_id {
+                holders
+                    .entry(opaque_id.clone())
+                    .or_default()
+                    .values
+                    .push(i as u32);
+            }
+        }
+        for (i, entry) in asn_entries.iter().enumerate() {
+            if let Some(opaque_id) = &entry.opaque_id {
+                holders
+                    .entry(opaque_id.clone())
+                    .or_default()
+                    .asns
+                    .push(i as u32);
+            }
+        }
 
-                info!("Loaded ARIN stats!");
-                stat_entries.sort();
+        info!("Loaded ARIN stats!");
 
-                StatsProvider {
-                    values: stat_entries,
-                    sources,
-                }
+        Ok(StatsProvider {
+            index: CidrIndex::build(stat_entries.iter().map(|x| &x.cidr)),
+            values: stat_entries,
+            asn_index: PrefixIndex::build(
+                asn_entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (x.start, x.end(), i as u32))
+                    .collect(),
+            ),
+            asns: asn_entries,
+            holders,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: StatsProvider = providers::snapshot::decode(data)?;
+        Some(StatsProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        let entry = self.lookup(address)?;
+
+        match kind {
+            LookupKind::AllocationState => {
+                Some(LookupValue::AllocationState(entry.allocation_state.clone()))
             }
-            None => panic!("Failed to load sources for ARIN stats!"),
+            LookupKind::Rir => Some(LookupValue::Rir(entry.rir.clone())),
+            LookupKind::Country => entry.country.clone().map(LookupValue::Country),
+            LookupKind::RegistrationDate => entry
+                .registration_date
+                .clone()
+                .map(LookupValue::RegistrationDate),
+            LookupKind::OpaqueId => entry.opaque_id.clone().map(LookupValue::OpaqueId),
+            _ => None,
         }
     }
+
+    fn autsys(&self, asn: u32) -> Option<AsnResponse> {
+        self.lookup_asn(asn).map(|entry| AsnResponse {
+            asn,
+            allocation_state: entry.allocation_state.clone(),
+            rir: entry.rir.clone(),
+            country: entry.country.clone(),
+            registration_date: entry.registration_date.clone(),
+            opaque_id: entry.opaque_id.clone(),
+        })
+    }
+
+    fn holder(&self, opaque_id: &str) -> Option<HolderResponse> {
+        self.lookup_holder(opaque_id)
+            .map(|(prefixes, asns)| HolderResponse {
+                opaque_id: opaque_id.to_string(),
+                prefixes: prefixes
+                    .into_iter()
+                    .map(|entry| PrefixBlock {
+                        cidr: entry.cidr.to_string(),
+                        allocation_state: entry.allocation_state.clone(),
+                        rir: entry.rir.clone(),
+                        country: entry.country.clone(),
+                        registration_date: entry.registration_date.clone(),
+                    })
+                    .collect(),
+                asns: asns
+                    .into_iter()
+                    .map(|entry| AsnBlock {
+                        start: entry.start,
+                        count: entry.count,
+                        allocation_state: entry.allocation_state.clone(),
+                        rir: entry.rir.clone(),
+                        country: entry.country.clone(),
+                        registration_date: entry.registration_date.clone(),
+                    })
+                    .collect(),
+            })
+    }
+
+    fn prefixes(&self, filter: &PrefixFilter) -> Option<Vec<CIDR>> {
+        match filter {
+            PrefixFilter::Country(country) => Some(
+                self.values
+                    .iter()
+                    .filter(|entry| {
+                        entry
+                            .country
+                            .as_ref()
+                            .is_some_and(|x| x.eq_ignore_ascii_case(country))
+                    })
+                    .map(|entry| entry.cidr)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| (entry.cidr.first_address(), entry.cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len() + self.asns.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use mtilib::types::{AllocationState, Rir};
+
+    use super::{parse_record, StatsProvider, StatsRecord};
+    use crate::providers::{LoadError, Provider, ProviderSource};
+
+    fn fixture_source() -> ProviderSource {
+        ProviderSource::fixture("delegated-apnic-extended.txt")
+    }
+
+    #[tokio::test]
+    async fn test_load_ipv4_and_ipv6() {
+        let provider = StatsProvider::load_sources(vec![fixture_source()])
+            .await
+            .unwrap();
+        assert_eq!(provider.values.len(), 4);
+
+        let entry = provider
+            .lookup(IpAddr::from_str("1.0.0.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.allocation_state, AllocationState::Allocated);
+        assert_eq!(entry.country, Some(String::from("AU")));
+
+        let entry = provider
+            .lookup(IpAddr::from_str("2001:200::1").unwrap())
+            .unwrap();
+        assert_eq!(entry.cidr.mask, 35);
+        assert_eq!(entry.rir, Rir::Apnic);
+        assert_eq!(entry.country, Some(String::from("JP")));
+
+        let entry = provider
+            .lookup(IpAddr::from_str("2001:200:e000::1").unwrap())
+            .unwrap();
+        assert_eq!(entry.allocation_state, AllocationState::Unallocated);
+
+        assert!(provider
+            .lookup(IpAddr::from_str("2001:db8::1").unwrap())
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_asns() {
+        let provider = StatsProvider::load_sources(vec![fixture_source()])
+            .await
+            .unwrap();
+        assert_eq!(provider.asns.len(), 3);
+
+        let entry = provider.lookup_asn(173).unwrap();
+        assert_eq!(entry.country, Some(String::from("JP")));
+        assert_eq!(entry.registration_date, Some(String::from("2002-08-01")));
+
+        let entry = provider.lookup_asn(4610).unwrap();
+        assert_eq!(entry.start, 4608);
+        assert_eq!(entry.rir, Rir::Apnic);
+
+        let entry = provider.lookup_asn(9000).unwrap();
+        assert_eq!(entry.allocation_state, AllocationState::Unallocated);
+        assert_eq!(entry.registration_date, None);
+
+        assert!(provider.lookup_asn(174).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_holders() {
+        let provider = StatsProvider::load_sources(vec![fixture_source()])
+            .await
+            .unwrap();
+
+        let entry = provider
+            .lookup(IpAddr::from_str("1.0.0.1").unwrap())
+            .unwrap();
+        assert_eq!(entry.registration_date, Some(String::from("2011-08-11")));
+        assert_eq!(entry.opaque_id, Some(String::from("A91872ED")));
+
+        let (prefixes, asns) = provider.lookup_holder("A91A7381").unwrap();
+        assert_eq!(prefixes.len(), 1);
+        assert_eq!(prefixes[0].cidr.to_string(), "2001:200::/35");
+        assert_eq!(asns.len(), 1);
+        assert_eq!(asns[0].start, 173);
+
+        assert!(provider.lookup_holder("A0000000").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_malformed() {
+        let provider = StatsProvider::load_sources(vec![ProviderSource::fixture(
+            "delegated-apnic-malformed.txt",
+        )])
+        .await
+        .unwrap();
+        assert_eq!(provider.values.len(), 2);
+        assert_eq!(provider.asns.len(), 1);
+        assert_eq!(provider.asns[0].start, 174);
+
+        assert_eq!(provider.skipped.count, 6);
+        assert_eq!(
+            provider
+                .skipped
+                .lines
+                .iter()
+                .map(|x| x.line)
+                .collect::<Vec<_>>(),
+            vec![5, 6, 7, 8, 9, 10]
+        );
+        assert_eq!(provider.skipped.lines[0].reason, "bad address 1.0.1.x");
+        assert_eq!(
+            provider.skipped.lines[2].reason,
+            "unknown status confiscated"
+        );
+    }
+
+    #[test]
+    fn test_uneven_ipv4_block() {
+        // 768 addresses are a /23 and a /24, both with the details of the record
+        let record = "apnic|JP|ipv4|1.0.16.0|768|20110412|allocated|A92E1062";
+        let entries = match parse_record(&record.split('|').collect::<Vec<_>>()) {
+            Ok(Some(StatsRecord::Addresses(entries))) => entries,
+            _ => panic!("Expected an address record!"),
+        };
+        assert_eq!(
+            entries
+                .iter()
+                .map(|x| x.cidr.to_string())
+                .collect::<Vec<_>>(),
+            vec!["1.0.16.0/23", "1.0.18.0/24"]
+        );
+        assert!(entries
+            .iter()
+            .all(|x| x.country.as_deref() == Some("JP")
+                && x.opaque_id.as_deref() == Some("A92E1062")));
+
+        let record = "apnic|JP|ipv4|255.255.255.0|512|20110412|allocated";
+        assert!(parse_record(&record.split('|').collect::<Vec<_>>()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_missing() {
+        let result =
+            StatsProvider::load_sources(vec![ProviderSource::fixture("does-not-exist.txt")]).await;
+        assert!(matches!(result, Err(LoadError::Io { .. })));
+    }
 }
@@ -0,0 +1,162 @@
+// Names of the organisations using the AS numbers (data-used-autnums)
+//
+// Each line is the AS number followed by the name and the country, e.g.
+// "  13335 CLOUDFLARENET - Cloudflare, Inc., US".
+
+use std::net::IpAddr;
+
+use mtilib::types::AsnNameResponse;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::providers::{
+    self, LoadError, LookupKind, LookupValue, Provider, ProviderSource, ProviderStats, SkippedLines,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AsNameEntry {
+    pub asn: u32,
+    pub name: String,
+    pub country: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AsNamesProvider {
+    /// Sorted by the AS number
+    pub values: Vec<AsNameEntry>,
+    #[serde(skip)]
+    pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
+}
+
+impl AsNamesProvider {
+    pub fn lookup(&self, asn: u32) -> Option<&AsNameEntry> {
+        self.values
+            .binary_search_by_key(&asn, |entry| entry.asn)
+            .ok()
+            .map(|i| &self.values[i])
+    }
+}
+
+// The country is the last comma separated part of the name, when it looks like one
+fn parse_name(name: &str) -> (String, Option<String>) {
+    match name.rsplit_once(',') {
+        Some((name, country))
+            if country.trim().len() == 2
+                && country.trim().bytes().all(|x| x.is_ascii_uppercase()) =>
+        {
+            (name.trim().to_string(), Some(country.trim().to_string()))
+        }
+        _ => (name.trim().to_string(), None),
+    }
+}
+
+impl Provider for AsNamesProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
+        info!("Loading AS names...");
+
+        let re = Regex::new(r"^\s*(\d+)\s+(.*)$").unwrap();
+        let mut names = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents_str = providers::read_source(source).await?;
+
+            // The lines which don't start with a number aren't records
+            for (i, line) in contents_str.lines().enumerate() {
+                let Some((_, [asn, name])) = re.captures(line).map(|c| c.extract()) else {
+                    continue;
+                };
+
+                let Ok(asn) = asn.parse() else {
+                    skipped.skip(source, i + 1, format!("bad asn {}", asn));
+                    continue;
+                };
+                let (name, country) = parse_name(name);
+                if name.is_empty() {
+                    skipped.skip(source, i + 1, "missing name");
+                    continue;
+                }
+
+                names.push(AsNameEntry { asn, name, country });
+            }
+        }
+
+        // The first source listing the AS number wins
+        names.sort_by_key(|entry| entry.asn);
+        names.dedup_by_key(|entry| entry.asn);
+
+        info!("Loaded AS names!");
+
+        Ok(AsNamesProvider {
+            values: names,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: AsNamesProvider = providers::snapshot::decode(data)?;
+        Some(AsNamesProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, _kind: LookupKind, _address: IpAddr) -> Option<LookupValue> {
+        None
+    }
+
+    fn autsys_name(&self, asn: u32) -> Option<AsnNameResponse> {
+        self.lookup(asn).map(|entry| AsnNameResponse {
+            asn: entry.asn,
+            name: entry.name.clone(),
+            country: entry.country.clone(),
+        })
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsNamesProvider;
+    use crate::providers::{Provider, ProviderSource};
+
+    #[tokio::test]
+    async fn test_load() {
+        let provider =
+            AsNamesProvider::load_sources(vec![ProviderSource::fixture("data-used-autnums.txt")])
+                .await
+                .unwrap();
+        assert_eq!(provider.values.len(), 5);
+        assert_eq!(provider.skipped.count, 1);
+
+        let name = provider.autsys_name(13335).unwrap();
+        assert_eq!(name.name, "CLOUDFLARENET - Cloudflare, Inc.");
+        assert_eq!(name.country.as_deref(), Some("US"));
+
+        // Names without a country are kept as they are
+        let name = provider.autsys_name(64496).unwrap();
+        assert_eq!(name.name, "EXAMPLE-AS - Documentation, Example");
+        assert_eq!(name.country, None);
+
+        assert!(provider.autsys_name(64511).is_none());
+    }
+}
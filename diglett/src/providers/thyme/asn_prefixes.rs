@@ -1,61 +1,131 @@
-use std::{fmt::Display, path::Path, str::FromStr};
+use std::{fmt::Display, net::IpAddr, str::FromStr};
 
-use config::Config;
 use regex::Regex;
-use tokio::{fs::File, io::AsyncReadExt};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
-    providers::{self, CheckAndDownloadSource, ProviderSource},
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, PrefixFilter, Provider,
+        ProviderSource, ProviderStats, SkippedLines,
+    },
     utils::CIDR,
 };
 
+#[derive(Serialize, Deserialize)]
 pub struct AsnPrefixesProvider {
     pub values: Vec<AsnPrefixEntry>,
+    pub index: CidrIndex,
+    #[serde(skip)]
     pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
 }
 
 impl AsnPrefixesProvider {
-    pub async fn load(config: &Config) -> Self {
+    pub fn lookup(&self, address: IpAddr) -> Option<&AsnPrefixEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
+}
+
+impl Provider for AsnPrefixesProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
         info!("Loading ASN prefixes...");
 
-        // Check if we need to redownload file
-        match providers::load_provider_sources(config, "thyme.asn_prefixes") {
-            Some(sources) => {
-                sources.check_and_download().await;
-
-                let mut prefixes = Vec::new();
-                for source in sources.iter() {
-                    let asn_prefixes_filepath = Path::new(&source.filepath);
-
-                    let mut file = File::open(asn_prefixes_filepath).await.unwrap();
-                    let mut contents_str = String::new();
-                    file.read_to_string(&mut contents_str).await.unwrap();
-
-                    let re = Regex::new(r"([\d\.]+\/\d{1,2})[\t ]+(\d+)").unwrap();
-                    for (_, [prefix, asn]) in re.captures_iter(&contents_str).map(|c| c.extract()) {
-                        let parsed_cidr = CIDR::from_str(prefix).unwrap();
-                        prefixes.push(AsnPrefixEntry {
-                            cidr: parsed_cidr,
-                            asn: asn.parse().unwrap(),
-                        });
-                    }
-                }
+        let re = Regex::new(r"([\da-fA-F\.:]+\/\d{1,3})[\t ]+(\d+)").unwrap();
+        let mut prefixes = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents_str = providers::read_source(source).await?;
 
-                info!("Loaded ASN prefixes!");
-                prefixes.sort();
+            // The lines which don't look like "<prefix> <asn>" at all aren't records
+            for (i, line) in contents_str.lines().enumerate() {
+                let Some((_, [prefix, asn])) = re.captures(line).map(|c| c.extract()) else {
+                    continue;
+                };
 
-                AsnPrefixesProvider {
-                    values: prefixes,
-                    sources,
+                match (CIDR::from_str(prefix), asn.parse()) {
+                    (Ok(cidr), Ok(asn)) => prefixes.push(AsnPrefixEntry { cidr, asn }),
+                    (Err(error), _) => {
+                        skipped.skip(source, i + 1, format!("{} ({})", error, prefix))
+                    }
+                    (_, Err(_)) => skipped.skip(source, i + 1, format!("bad asn {}", asn)),
                 }
             }
-            None => panic!("Failed to load sources for Thyme's ASN prefixes"),
+        }
+
+        info!("Loaded ASN prefixes!");
+
+        Ok(AsnPrefixesProvider {
+            index: CidrIndex::build(prefixes.iter().map(|x| &x.cidr)),
+            values: prefixes,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: AsnPrefixesProvider = providers::snapshot::decode(data)?;
+        Some(AsnPrefixesProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::Asn => self
+                .lookup(address)
+                .map(|entry| LookupValue::Asn(entry.asn)),
+            LookupKind::Routed => self.lookup(address).map(|entry| LookupValue::Route {
+                prefix: entry.cidr.to_string(),
+                origins: vec![entry.asn],
+            }),
+            _ => None,
+        }
+    }
+
+    fn prefixes(&self, filter: &PrefixFilter) -> Option<Vec<CIDR>> {
+        match filter {
+            PrefixFilter::Asn(asn) => Some(
+                self.values
+                    .iter()
+                    .filter(|entry| entry.asn == *asn)
+                    .map(|entry| entry.cidr)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| (entry.cidr.first_address(), entry.cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AsnPrefixEntry {
     pub cidr: CIDR,
     pub asn: u32,
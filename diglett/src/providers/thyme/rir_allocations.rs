@@ -1,62 +1,128 @@
-use std::{fmt::Display, path::Path, str::FromStr};
+use std::{fmt::Display, net::IpAddr, str::FromStr};
 
-use config::Config;
 use mtilib::types::Rir;
 use regex::Regex;
-use tokio::{fs::File, io::AsyncReadExt};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
-    providers::{self, CheckAndDownloadSource, ProviderSource},
+    providers::{
+        self, CidrIndex, LoadError, LookupKind, LookupValue, PrefixFilter, Provider,
+        ProviderSource, ProviderStats, SkippedLines,
+    },
     utils::CIDR,
 };
 
+#[derive(Serialize, Deserialize)]
 pub struct RirAllocationsProvider {
     pub values: Vec<RirAllocationEntry>,
+    pub index: CidrIndex,
+    #[serde(skip)]
     pub sources: Vec<ProviderSource>,
+    pub skipped: SkippedLines,
 }
 
 impl RirAllocationsProvider {
-    pub async fn load(config: &Config) -> Self {
+    pub fn lookup(&self, address: IpAddr) -> Option<&RirAllocationEntry> {
+        self.index.lookup(address).map(|i| &self.values[i as usize])
+    }
+}
+
+impl Provider for RirAllocationsProvider {
+    async fn load_sources(sources: Vec<ProviderSource>) -> Result<Self, LoadError> {
         info!("Loading RIR allocations...");
 
-        // Check if we need to redownload file
-        match providers::load_provider_sources(config, "thyme.rir_allocations") {
-            Some(sources) => {
-                sources.check_and_download().await;
-
-                let mut rir_allocations = Vec::new();
-                for source in sources.iter() {
-                    let rir_allocations_filepath = Path::new(&source.filepath);
-
-                    let mut file: File = File::open(rir_allocations_filepath).await.unwrap();
-                    let mut contents_str = String::new();
-                    file.read_to_string(&mut contents_str).await.unwrap();
-
-                    let re = Regex::new(r"[\t ]+(\d+\/\d)[\t ]+(.+)").unwrap();
-                    for (_, [prefix, rir]) in re.captures_iter(&contents_str).map(|c| c.extract()) {
-                        let parsed_cidr = CIDR::from_str(prefix).unwrap();
-                        rir_allocations.push(RirAllocationEntry {
-                            cidr: parsed_cidr,
-                            rir: Rir::from_str(rir).unwrap(),
-                        });
-                    }
-                }
+        let re = Regex::new(r"[\t ]+(\d+\/\d)[\t ]+(.+)").unwrap();
+        let mut rir_allocations = Vec::new();
+        let mut skipped = SkippedLines::default();
+        for source in sources.iter() {
+            let contents_str = providers::read_source(source).await?;
 
-                info!("Loaded RIR allocations!");
-                rir_allocations.sort();
+            // The lines which don't look like "<prefix> <rir>" at all aren't records
+            for (i, line) in contents_str.lines().enumerate() {
+                let Some((_, [prefix, rir])) = re.captures(line).map(|c| c.extract()) else {
+                    continue;
+                };
 
-                RirAllocationsProvider {
-                    values: rir_allocations,
-                    sources,
+                match (CIDR::from_str(prefix), Rir::from_str(rir.trim())) {
+                    (Ok(cidr), Ok(rir)) => rir_allocations.push(RirAllocationEntry { cidr, rir }),
+                    (Err(error), _) => {
+                        skipped.skip(source, i + 1, format!("{} ({})", error, prefix))
+                    }
+                    (_, Err(_)) => skipped.skip(source, i + 1, format!("unknown registry {}", rir)),
                 }
             }
-            None => panic!("Failed to load sources for Thyme's RIR allocations!"),
+        }
+
+        info!("Loaded RIR allocations!");
+
+        Ok(RirAllocationsProvider {
+            index: CidrIndex::build(rir_allocations.iter().map(|x| &x.cidr)),
+            values: rir_allocations,
+            sources,
+            skipped,
+        })
+    }
+
+    fn sources(&self) -> &Vec<ProviderSource> {
+        &self.sources
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        providers::snapshot::encode(self)
+    }
+
+    fn restore(data: &[u8], sources: Vec<ProviderSource>) -> Option<Self> {
+        let provider: RirAllocationsProvider = providers::snapshot::decode(data)?;
+        Some(RirAllocationsProvider {
+            sources,
+            ..provider
+        })
+    }
+
+    fn skipped(&self) -> Option<&SkippedLines> {
+        Some(&self.skipped)
+    }
+
+    fn lookup(&self, kind: LookupKind, address: IpAddr) -> Option<LookupValue> {
+        match kind {
+            LookupKind::TopRir => self
+                .lookup(address)
+                .map(|entry| LookupValue::Rir(entry.rir.clone())),
+            _ => None,
+        }
+    }
+
+    fn prefixes(&self, filter: &PrefixFilter) -> Option<Vec<CIDR>> {
+        match filter {
+            PrefixFilter::Rir(rir) => Some(
+                self.values
+                    .iter()
+                    .filter(|entry| entry.rir == *rir)
+                    .map(|entry| entry.cidr)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn ranges(&self) -> Option<Vec<(IpAddr, IpAddr)>> {
+        Some(
+            self.values
+                .iter()
+                .map(|entry| (entry.cidr.first_address(), entry.cidr.last_address()))
+                .collect(),
+        )
+    }
+
+    fn stats(&self) -> ProviderStats {
+        ProviderStats {
+            entries: self.values.len(),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RirAllocationEntry {
     pub cidr: CIDR,
     pub rir: Rir,
@@ -78,9 +144,7 @@ impl Display for RirAllocationEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
             "address: {}, mask: {}, rir: {}",
-            self.cidr.prefix,
-            self.cidr.mask,
-            self.rir
+            self.cidr.prefix, self.cidr.mask, self.rir
         ))
     }
 }
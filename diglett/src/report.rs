@@ -0,0 +1,414 @@
+// Cross-source consistency report
+//
+// The address space is split into segments at the boundaries of the ranges of all of the providers,
+// so that every provider gives the same answer for the whole segment. Each segment is checked once
+// and the neighbouring segments with the same disagreement are merged back together.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use config::Config;
+use mtilib::types::{AllocationState, Rir};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::providers::{LookupKind, Providers};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InconsistencyKind {
+    /// Announced in BGP, but unallocated, reserved or a bogon
+    RoutedUnallocated,
+    /// Announced in BGP by an AS number which isn't allocated
+    UnallocatedOrigin,
+    /// The RIR the block was delegated by isn't the one IANA allocated it to, outside the recovered space
+    RirMismatch,
+}
+
+impl InconsistencyKind {
+    pub const ALL: [InconsistencyKind; 3] = [
+        InconsistencyKind::RoutedUnallocated,
+        InconsistencyKind::UnallocatedOrigin,
+        InconsistencyKind::RirMismatch,
+    ];
+
+    pub fn id(&self) -> &str {
+        match self {
+            InconsistencyKind::RoutedUnallocated => "routed_unallocated",
+            InconsistencyKind::UnallocatedOrigin => "unallocated_origin",
+            InconsistencyKind::RirMismatch => "rir_mismatch",
+        }
+    }
+}
+
+impl FromStr for InconsistencyKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InconsistencyKind::ALL
+            .into_iter()
+            .find(|kind| kind.id() == s)
+            .ok_or(())
+    }
+}
+
+/// A range of addresses the sources disagree about, along with what each of them says.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inconsistency {
+    pub kind: InconsistencyKind,
+    pub start: IpAddr,
+    pub end: IpAddr,
+    pub allocation_state: AllocationState,
+    pub top_rir: Option<Rir>,
+    pub rir: Option<Rir>,
+    pub route: Option<String>,
+    pub origins: Vec<u32>,
+}
+
+impl Inconsistency {
+    // Whether the inconsistency continues with the other one
+    fn continues(&self, other: &Inconsistency) -> bool {
+        self.kind == other.kind
+            && self.allocation_state == other.allocation_state
+            && self.top_rir == other.top_rir
+            && self.rir == other.rir
+            && self.route == other.route
+            && self.origins == other.origins
+            && match (self.end, other.start) {
+                (IpAddr::V4(end), IpAddr::V4(start)) => {
+                    end.to_bits().checked_add(1) == Some(start.to_bits())
+                }
+                (IpAddr::V6(end), IpAddr::V6(start)) => {
+                    end.to_bits().checked_add(1) == Some(start.to_bits())
+                }
+                _ => false,
+            }
+    }
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rir = |rir: &Option<Rir>| rir.as_ref().map_or(String::from("-"), |x| x.to_string());
+
+        write!(
+            f,
+            "{} {} - {}: allocation_state: {}, top_rir: {}, rir: {}, route: {}, origins: {:?}",
+            self.kind.id(),
+            self.start,
+            self.end,
+            self.allocation_state.id(),
+            rir(&self.top_rir),
+            rir(&self.rir),
+            self.route.as_deref().unwrap_or("-"),
+            self.origins
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+    /// Number of the inconsistencies of each kind
+    pub counts: BTreeMap<InconsistencyKind, usize>,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+// All of the disagreements about a single segment
+fn check(
+    providers: &Providers,
+    start: IpAddr,
+    end: IpAddr,
+    inconsistencies: &mut Vec<Inconsistency>,
+) {
+    let allocation_state = providers.allocation_state(start);
+    let top_rir = providers.top_rir(start);
+    let rir = providers.rir(start);
+    let routed = providers.routed(start);
+
+    let inconsistency = |kind| Inconsistency {
+        kind,
+        start,
+        end,
+        allocation_state: allocation_state.clone(),
+        top_rir: top_rir.clone(),
+        rir: rir.clone(),
+        route: routed.prefix.clone(),
+        origins: routed.origins.clone(),
+    };
+
+    // Unknown means that none of the sources has the address, not that it isn't allocated
+    if routed.routed
+        && matches!(
+            allocation_state,
+            AllocationState::Unallocated | AllocationState::Reserved | AllocationState::Bogon
+        )
+    {
+        inconsistencies.push(inconsistency(InconsistencyKind::RoutedUnallocated));
+    }
+
+    // The AS numbers missing from the stats altogether are left out, they usually mean there are no stats loaded
+    if routed.origins.iter().any(|origin| {
+        providers
+            .autsys(*origin)
+            .is_some_and(|autsys| autsys.allocation_state != AllocationState::Allocated)
+    }) {
+        inconsistencies.push(inconsistency(InconsistencyKind::UnallocatedOrigin));
+    }
+
+    // The recovered blocks are redistributed by IANA to all of the RIRs, whatever the /8 says
+    let recovered = providers
+        .of_type("iana.recovered")
+        .any(|provider| provider.lookup(LookupKind::Rir, start).is_some());
+    if !recovered && top_rir.is_some() && rir.is_some() && top_rir != rir {
+        inconsistencies.push(inconsistency(InconsistencyKind::RirMismatch));
+    }
+}
+
+// Checks the segments between the sorted boundaries, the last one reaches to the end of the address space
+fn walk(
+    providers: &Providers,
+    boundaries: Vec<u128>,
+    max: u128,
+    address: impl Fn(u128) -> IpAddr,
+    inconsistencies: &mut Vec<Inconsistency>,
+) {
+    for (i, start) in boundaries.iter().enumerate() {
+        let end = boundaries.get(i + 1).map_or(max, |next| next - 1);
+        check(providers, address(*start), address(end), inconsistencies);
+    }
+}
+
+/// Walks the address space of all of the providers and lists the disagreements between them.
+pub fn build(providers: &Providers, kind: Option<InconsistencyKind>) -> Report {
+    let mut v4_boundaries = Vec::new();
+    let mut v6_boundaries = Vec::new();
+    for range in providers.ranges() {
+        match range {
+            (IpAddr::V4(start), IpAddr::V4(end)) => {
+                v4_boundaries.push(start.to_bits() as u128);
+                v4_boundaries.extend(end.to_bits().checked_add(1).map(|x| x as u128));
+            }
+            (IpAddr::V6(start), IpAddr::V6(end)) => {
+                v6_boundaries.push(start.to_bits());
+                v6_boundaries.extend(end.to_bits().checked_add(1));
+            }
+            _ => {}
+        }
+    }
+    for boundaries in [&mut v4_boundaries, &mut v6_boundaries] {
+        boundaries.sort_unstable();
+        boundaries.dedup();
+    }
+
+    let mut segments = Vec::new();
+    walk(
+        providers,
+        v4_boundaries,
+        u32::MAX as u128,
+        |x| IpAddr::V4(Ipv4Addr::from_bits(x as u32)),
+        &mut segments,
+    );
+    walk(
+        providers,
+        v6_boundaries,
+        u128::MAX,
+        |x| IpAddr::V6(Ipv6Addr::from_bits(x)),
+        &mut segments,
+    );
+
+    let mut report = Report::default();
+    let mut open: BTreeMap<InconsistencyKind, Inconsistency> = BTreeMap::new();
+    for segment in segments {
+        if kind.is_some_and(|kind| kind != segment.kind) {
+            continue;
+        }
+
+        match open.get_mut(&segment.kind) {
+            Some(last) if last.continues(&segment) => last.end = segment.end,
+            _ => {
+                if let Some(last) = open.insert(segment.kind, segment) {
+                    report.inconsistencies.push(last);
+                }
+            }
+        }
+    }
+    report.inconsistencies.extend(open.into_values());
+
+    report.inconsistencies.sort_by_key(|x| (x.start, x.kind));
+    for inconsistency in report.inconsistencies.iter() {
+        *report.counts.entry(inconsistency.kind).or_default() += 1;
+    }
+
+    report
+}
+
+/// Loads the providers and prints the report (`diglett report [kind]`).
+pub async fn run(config: &Config, kind: Option<&str>) {
+    let kind = match kind.map(InconsistencyKind::from_str) {
+        Some(Ok(kind)) => Some(kind),
+        Some(Err(_)) => {
+            error!(
+                "Unknown inconsistency kind, expected one of {}!",
+                InconsistencyKind::ALL
+                    .map(|kind| kind.id().to_string())
+                    .join(", ")
+            );
+            return;
+        }
+        None => None,
+    };
+
+    let providers = Providers::load(config).await;
+    let report = build(&providers, kind);
+
+    for inconsistency in report.inconsistencies.iter() {
+        println!("{}", inconsistency);
+    }
+    for (kind, count) in report.counts.iter() {
+        info!("Found {} inconsistencies of kind {}!", count, kind.id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        str::FromStr,
+    };
+
+    use mtilib::types::{AllocationState, Rir};
+
+    use super::{build, InconsistencyKind};
+    use crate::{
+        index::PrefixIndex,
+        providers::{
+            iana::recovered::{RecoveredEntry, RecoveredProvider},
+            stats::{AsnEntry, StatsEntry, StatsProvider},
+            thyme::{
+                asn_prefixes::{AsnPrefixEntry, AsnPrefixesProvider},
+                rir_allocations::{RirAllocationEntry, RirAllocationsProvider},
+            },
+            CidrIndex, Provider, Providers,
+        },
+        utils::CIDR,
+    };
+
+    fn stats_entry(cidr: &str, allocation_state: AllocationState, rir: Rir) -> StatsEntry {
+        StatsEntry {
+            cidr: CIDR::from_str(cidr).unwrap(),
+            allocation_state,
+            rir,
+            country: None,
+            registration_date: None,
+            opaque_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build() {
+        let mut stats = StatsProvider::load_sources(vec![]).await.unwrap();
+        stats.values = vec![
+            stats_entry("1.0.0.0/24", AllocationState::Allocated, Rir::Apnic),
+            stats_entry("1.0.1.0/24", AllocationState::Unallocated, Rir::Apnic),
+            stats_entry("2.0.0.0/16", AllocationState::Allocated, Rir::Arin),
+            stats_entry("45.0.0.0/16", AllocationState::Allocated, Rir::Lacnic),
+        ];
+        stats.index = CidrIndex::build(stats.values.iter().map(|x| &x.cidr));
+        stats.asns.push(AsnEntry {
+            start: 64496,
+            count: 1,
+            allocation_state: AllocationState::Reserved,
+            rir: Rir::Arin,
+            country: None,
+            registration_date: None,
+            opaque_id: None,
+        });
+        stats.asn_index = PrefixIndex::build(vec![(64496, 64496, 0)]);
+
+        let mut rir_allocations = RirAllocationsProvider::load_sources(vec![]).await.unwrap();
+        for (cidr, rir) in [
+            ("1.0.0.0/8", Rir::Apnic),
+            ("2.0.0.0/8", Rir::Ripencc),
+            ("45.0.0.0/8", Rir::Arin),
+        ] {
+            rir_allocations.values.push(RirAllocationEntry {
+                cidr: CIDR::from_str(cidr).unwrap(),
+                rir,
+            });
+        }
+        rir_allocations.index = CidrIndex::build(rir_allocations.values.iter().map(|x| &x.cidr));
+
+        let mut recovered = RecoveredProvider::load_sources(vec![]).await.unwrap();
+        recovered.values.push(RecoveredEntry {
+            start: Ipv4Addr::new(45, 0, 0, 0),
+            end: Ipv4Addr::new(45, 0, 255, 255),
+            rir: Rir::Lacnic,
+        });
+        recovered.index = CidrIndex::from_ranges(
+            recovered
+                .values
+                .iter()
+                .map(|x| (IpAddr::V4(x.start), IpAddr::V4(x.end))),
+        );
+
+        let mut asn_prefixes = AsnPrefixesProvider::load_sources(vec![]).await.unwrap();
+        for (cidr, asn) in [("1.0.0.0/23", 13335), ("2.0.0.0/24", 64496)] {
+            asn_prefixes.values.push(AsnPrefixEntry {
+                cidr: CIDR::from_str(cidr).unwrap(),
+                asn,
+            });
+        }
+        asn_prefixes.index = CidrIndex::build(asn_prefixes.values.iter().map(|x| &x.cidr));
+
+        let mut providers = Providers::empty();
+        providers.insert("arin.stats", stats);
+        providers.insert("thyme.rir_allocations", rir_allocations);
+        providers.insert("iana.recovered", recovered);
+        providers.insert("thyme.asn_prefixes", asn_prefixes);
+
+        let report = build(&providers, None);
+        let found = report
+            .inconsistencies
+            .iter()
+            .map(|x| (x.kind, x.start.to_string(), x.end.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    InconsistencyKind::RoutedUnallocated,
+                    String::from("1.0.1.0"),
+                    String::from("1.0.1.255")
+                ),
+                (
+                    InconsistencyKind::UnallocatedOrigin,
+                    String::from("2.0.0.0"),
+                    String::from("2.0.0.255")
+                ),
+                // Both the routed and the unrouted part of the delegation, the recovered 45/16 is left out
+                (
+                    InconsistencyKind::RirMismatch,
+                    String::from("2.0.0.0"),
+                    String::from("2.0.0.255")
+                ),
+                (
+                    InconsistencyKind::RirMismatch,
+                    String::from("2.0.1.0"),
+                    String::from("2.0.255.255")
+                ),
+            ]
+        );
+        assert_eq!(report.counts[&InconsistencyKind::RirMismatch], 2);
+
+        let report = build(&providers, Some(InconsistencyKind::RoutedUnallocated));
+        assert_eq!(report.inconsistencies.len(), 1);
+        assert_eq!(
+            report.inconsistencies[0].route.as_deref(),
+            Some("1.0.0.0/23")
+        );
+        assert_eq!(report.inconsistencies[0].origins, vec![13335]);
+    }
+}
@@ -1,39 +1,99 @@
-// use std::{sync::Arc, time::Duration};
-
-// use clokwerk::{AsyncScheduler, TimeUnits};
-// use tokio::sync::{Mutex, RwLock};
-// use tracing::info;
-
-// use crate::providers::{CheckAndDownloadSource, Providers};
-
-// pub async fn run(providers: Arc<RwLock<Providers>>) {
-//     let scheduler = Arc::new(Mutex::new(AsyncScheduler::new()));
-
-//     // Main scheduler loop
-//     let cloned_scheduler = scheduler.clone();
-//     tokio::spawn(async move {
-//         loop {
-//             cloned_scheduler.lock().await.run_pending().await;
-//             tokio::time::sleep(Duration::from_millis(1000)).await;
-//         }
-//     });
-
-//     let locked_providers = providers.clone().write().await;
-//     for provider in locked_providers.list() {
-//         let cloned_providers = providers.clone();
-//         for source in provider.sources() {
-//             scheduler
-//                 .lock()
-//                 .await
-//                 .every((source.max_time + 120).seconds()) // We want to wait a bit longer so that the check fails if the file is stale and the download + load cycle takes a few seconds
-//                 .run(move || {
-//                     let cloned_provider = provider.clone();
-//                     let cloned_source = source.clone();
-//                     let cloned_providers = cloned_providers.clone();
-//                     async move {
-//                         cloned_source.check_and_download().await;
-//                     }
-//                 });
-//         }
-//     }
-// }
+use std::{sync::Arc, time::Duration};
+
+use clokwerk::{AsyncScheduler, TimeUnits};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::providers::{download::DownloadOutcome, ProviderSource, Providers};
+
+// How many times a source gets checked during its max_time, so it doesn't stay stale for long
+const CHECKS_PER_MAX_TIME: u32 = 10;
+const MIN_CHECK_INTERVAL: u32 = 60;
+
+async fn refresh(providers: Arc<RwLock<Providers>>, id: String, source: ProviderSource) {
+    if source.check().await {
+        return;
+    }
+
+    info!(
+        "Source {} of provider {} is stale, refreshing...",
+        source.filepath, id
+    );
+
+    // On failure the last good file stays in place, so there is nothing to reload
+    match source.download().await {
+        Ok(DownloadOutcome::Updated) => {}
+        Ok(DownloadOutcome::NotModified) => {
+            let outdated = {
+                let mut providers = providers.write().await;
+                providers.set_source_error(&id, &source.filepath, None);
+                providers.refresh_fingerprints(&id)
+            };
+            info!(
+                "Source {} of provider {} has not changed!",
+                source.filepath, id
+            );
+            if outdated {
+                Providers::save_snapshot_unlocked(&providers).await;
+            }
+            return;
+        }
+        Err(error) => {
+            error!(
+                "Failed to download source {} of provider {}, keeping the last good file! ({})",
+                source.filepath, id, error
+            );
+            providers.write().await.set_source_error(
+                &id,
+                &source.filepath,
+                Some(error.to_string()),
+            );
+            return;
+        }
+    }
+    providers
+        .write()
+        .await
+        .set_source_error(&id, &source.filepath, None);
+
+    match Providers::reload(&providers, &id).await {
+        Ok(_) => {
+            info!("Reloaded provider {}!", id);
+            Providers::save_snapshot_unlocked(&providers).await;
+        }
+        Err(error) => error!(
+            "Failed to reload provider {}, keeping the old data! ({})",
+            id, error
+        ),
+    }
+}
+
+pub async fn run(providers: Arc<RwLock<Providers>>) {
+    let mut scheduler = AsyncScheduler::new();
+
+    let ids = providers.read().await.ids();
+    for id in ids {
+        let sources = providers.read().await.sources(&id).unwrap().clone();
+
+        for source in sources {
+            // Sources which can't be downloaded only change through an import and a restart
+            if !source.can_download() {
+                continue;
+            }
+
+            let interval = (source.max_time / CHECKS_PER_MAX_TIME).max(MIN_CHECK_INTERVAL);
+            let job_providers = providers.clone();
+            let job_id = id.clone();
+
+            scheduler
+                .every(interval.seconds())
+                .run(move || refresh(job_providers.clone(), job_id.clone(), source.clone()));
+        }
+    }
+
+    // Main scheduler loop
+    loop {
+        scheduler.run_pending().await;
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+    }
+}
@@ -6,4 +6,29 @@ pub struct Settings {
     pub api: SettingsAPI,
     pub pokedex: SettingsPokedex,
     pub unit: SettingsUnit,
+    pub whois: Option<SettingsWhois>,
+    pub dns: Option<SettingsDns>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettingsWhois {
+    pub port: u16,
+    #[serde(default = "_default_whois_timeout")]
+    pub timeout: u64,
+}
+
+const fn _default_whois_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettingsDns {
+    pub port: u16,
+    pub zone: String,
+    #[serde(default = "_default_dns_ttl")]
+    pub ttl: u32,
+}
+
+const fn _default_dns_ttl() -> u32 {
+    300
 }
@@ -1,29 +1,60 @@
-use std::{fmt::Display, net::Ipv4Addr, str::FromStr};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CIDR {
-    pub prefix: u32,
+    pub prefix: IpAddr,
     pub mask: u16,
 }
 
 impl CIDR {
-    pub fn new(prefix: u32, mask: u16) -> Self {
+    pub fn new(prefix: IpAddr, mask: u16) -> Self {
         CIDR { prefix, mask }
     }
 
-    pub fn address_is_in(&self, address: u32) -> bool {
-        let mask = u32::MAX << (32 - self.mask);
-        (self.prefix & mask) == (address & mask)
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.first_address(), self.last_address(), address) {
+            (IpAddr::V4(first), IpAddr::V4(last), IpAddr::V4(address)) => {
+                first <= address && address <= last
+            }
+            (IpAddr::V6(first), IpAddr::V6(last), IpAddr::V6(address)) => {
+                first <= address && address <= last
+            }
+            _ => false,
+        }
+    }
+
+    pub fn first_address(&self) -> IpAddr {
+        match self.prefix {
+            IpAddr::V4(prefix) => IpAddr::V4(Ipv4Addr::from_bits(
+                prefix.to_bits() & u32::MAX.checked_shl(32 - self.mask as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(prefix) => IpAddr::V6(Ipv6Addr::from_bits(
+                prefix.to_bits() & u128::MAX.checked_shl(128 - self.mask as u32).unwrap_or(0),
+            )),
+        }
+    }
+
+    pub fn last_address(&self) -> IpAddr {
+        match self.prefix {
+            IpAddr::V4(prefix) => IpAddr::V4(Ipv4Addr::from_bits(
+                prefix.to_bits() | !u32::MAX.checked_shl(32 - self.mask as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(prefix) => IpAddr::V6(Ipv6Addr::from_bits(
+                prefix.to_bits() | !u128::MAX.checked_shl(128 - self.mask as u32).unwrap_or(0),
+            )),
+        }
     }
 }
 
 impl Display for CIDR {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!(
-            "{}/{}",
-            Ipv4Addr::from_bits(self.prefix),
-            self.mask
-        ))
+        f.write_str(&format!("{}/{}", self.prefix, self.mask))
     }
 }
 
@@ -32,6 +63,30 @@ impl FromStr for CIDR {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut parts = s.split("/").map(|s| s.to_string()).collect::<Vec<String>>();
+        if parts.len() != 2 {
+            return Err("Invalid CIDR format!");
+        }
+
+        let parsed_mask = match parts[1].parse::<u16>() {
+            Ok(mask) => mask,
+            Err(_) => return Err("Failed to convert mask to u16!"),
+        };
+
+        if parts[0].contains(":") {
+            let parsed_address = match Ipv6Addr::from_str(&parts[0]) {
+                Ok(address) => address,
+                Err(_) => return Err("Failed to parse IPv6 address!"),
+            };
+            if parsed_mask > 128 {
+                return Err("IPv6 mask must be at most 128!");
+            }
+
+            return Ok(CIDR {
+                prefix: IpAddr::V6(parsed_address),
+                mask: parsed_mask,
+            });
+        }
+
         if parts[0].split(".").count() == 1 {
             parts[0] += ".0";
         }
@@ -44,15 +99,14 @@ impl FromStr for CIDR {
 
         let parsed_address = match Ipv4Addr::from_str(&parts[0]) {
             Ok(address) => address,
-            Err(_) => return Err("Failed to convert address to u32!"),
-        };
-        let parsed_mask = match parts[1].parse::<u16>() {
-            Ok(mask) => mask,
-            Err(_) => return Err("Failed to convert mask to u16!"),
+            Err(_) => return Err("Failed to parse IPv4 address!"),
         };
+        if parsed_mask > 32 {
+            return Err("IPv4 mask must be at most 32!");
+        }
 
         Ok(CIDR {
-            prefix: parsed_address.into(),
+            prefix: IpAddr::V4(parsed_address),
             mask: parsed_mask,
         })
     }
@@ -70,16 +124,201 @@ impl Ord for CIDR {
     }
 }
 
+// Inclusive address range, both families as u128
+type Range = (u128, u128);
+
+// Address ranges of both families, sorted and with the overlapping or adjacent ones merged
+fn merged_ranges(cidrs: &[CIDR]) -> (Vec<Range>, Vec<Range>) {
+    let mut v4_ranges = Vec::new();
+    let mut v6_ranges = Vec::new();
+    for cidr in cidrs {
+        match (cidr.first_address(), cidr.last_address()) {
+            (IpAddr::V4(first), IpAddr::V4(last)) => {
+                v4_ranges.push((first.to_bits() as u128, last.to_bits() as u128))
+            }
+            (IpAddr::V6(first), IpAddr::V6(last)) => {
+                v6_ranges.push((first.to_bits(), last.to_bits()))
+            }
+            _ => {}
+        }
+    }
+
+    let merge = |mut ranges: Vec<Range>| {
+        ranges.sort();
+        let mut merged: Vec<Range> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    };
+
+    (merge(v4_ranges), merge(v6_ranges))
+}
+
+// Splits an inclusive range into the fewest prefixes, as (network, mask) pairs
+fn range_to_prefixes(mut start: u128, end: u128, bits: u32) -> Vec<(u128, u16)> {
+    let block_end = |start: u128, size: u32| match size {
+        128 => u128::MAX,
+        size => start | ((1 << size) - 1),
+    };
+
+    let mut prefixes = Vec::new();
+    loop {
+        // The largest block aligned at the start which doesn't go past the end
+        let mut size = start.trailing_zeros().min(bits);
+        while block_end(start, size) > end {
+            size -= 1;
+        }
+        prefixes.push((start, (bits - size) as u16));
+
+        let last = block_end(start, size);
+        if last >= end {
+            return prefixes;
+        }
+        start = last + 1;
+    }
+}
+
+/// Merges overlapping and adjacent prefixes into the fewest prefixes covering the same addresses.
+pub fn aggregate(cidrs: &[CIDR]) -> Vec<CIDR> {
+    let (v4_ranges, v6_ranges) = merged_ranges(cidrs);
+
+    let v4 = v4_ranges
+        .into_iter()
+        .flat_map(|(start, end)| range_to_prefixes(start, end, 32))
+        .map(|(network, mask)| CIDR::new(IpAddr::V4(Ipv4Addr::from_bits(network as u32)), mask));
+    let v6 = v6_ranges
+        .into_iter()
+        .flat_map(|(start, end)| range_to_prefixes(start, end, 128))
+        .map(|(network, mask)| CIDR::new(IpAddr::V6(Ipv6Addr::from_bits(network)), mask));
+
+    v4.chain(v6).collect()
+}
+
+/// Number of IPv4 and IPv6 addresses covered by the prefixes, the overlaps are counted once.
+pub fn address_count(cidrs: &[CIDR]) -> (u64, u128) {
+    let (v4_ranges, v6_ranges) = merged_ranges(cidrs);
+
+    (
+        v4_ranges
+            .into_iter()
+            .map(|(start, end)| (end - start + 1) as u64)
+            .sum(),
+        v6_ranges.into_iter().fold(0u128, |count, (start, end)| {
+            count.saturating_add((end - start).saturating_add(1))
+        }),
+    )
+}
+
+/// Splits an inclusive address range into the fewest prefixes covering it.
+pub fn range_prefixes(start: IpAddr, end: IpAddr) -> Vec<CIDR> {
+    match (start, end) {
+        (IpAddr::V4(start), IpAddr::V4(end)) => {
+            range_to_prefixes(start.to_bits() as u128, end.to_bits() as u128, 32)
+                .into_iter()
+                .map(|(network, mask)| {
+                    CIDR::new(IpAddr::V4(Ipv4Addr::from_bits(network as u32)), mask)
+                })
+                .collect()
+        }
+        (IpAddr::V6(start), IpAddr::V6(end)) => {
+            range_to_prefixes(start.to_bits(), end.to_bits(), 128)
+                .into_iter()
+                .map(|(network, mask)| CIDR::new(IpAddr::V6(Ipv6Addr::from_bits(network)), mask))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// The civil calendar conversions are from http://howardhinnant.github.io/date_algorithms.html
+
+/// Days since the unix epoch of a `YYYY-MM-DD` date, `None` when it isn't a valid date.
+pub fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    // Out of range days and months (e.g. 2024-02-30) don't survive the round trip
+    (format_date(days) == date).then_some(days)
+}
+
+/// The `YYYY-MM-DD` date of the days since the unix epoch.
+pub fn format_date(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{cmp::Ordering, net::Ipv4Addr, str::FromStr};
+    use std::{cmp::Ordering, net::IpAddr, str::FromStr};
 
-    use super::CIDR;
+    use super::{address_count, aggregate, format_date, parse_date, CIDR};
 
     #[test]
     fn test_address_cidr_check() {
         let prefix = CIDR::from_str("1.1.1.0/25").unwrap();
-        assert!(prefix.address_is_in(Ipv4Addr::from_str("1.1.1.127").unwrap().into()));
+        assert!(prefix.contains(IpAddr::from_str("1.1.1.127").unwrap()));
+        assert!(!prefix.contains(IpAddr::from_str("1.1.1.128").unwrap()));
+        assert!(!prefix.contains(IpAddr::from_str("::ffff:1.1.1.1").unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_range() {
+        let prefix = CIDR::from_str("10.1.2.3/8").unwrap();
+        assert_eq!(
+            prefix.first_address(),
+            IpAddr::from_str("10.0.0.0").unwrap()
+        );
+        assert_eq!(
+            prefix.last_address(),
+            IpAddr::from_str("10.255.255.255").unwrap()
+        );
+
+        let default_route = CIDR::from_str("0.0.0.0/0").unwrap();
+        assert_eq!(
+            default_route.first_address(),
+            IpAddr::from_str("0.0.0.0").unwrap()
+        );
+        assert_eq!(
+            default_route.last_address(),
+            IpAddr::from_str("255.255.255.255").unwrap()
+        );
+
+        let prefix_v6 = CIDR::from_str("2001:db8::1/32").unwrap();
+        assert_eq!(
+            prefix_v6.first_address(),
+            IpAddr::from_str("2001:db8::").unwrap()
+        );
+        assert_eq!(
+            prefix_v6.last_address(),
+            IpAddr::from_str("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff").unwrap()
+        );
     }
 
     #[test]
@@ -88,4 +327,41 @@ mod tests {
         let prefix_b = CIDR::from_str("1.0.0.0/8").unwrap();
         assert_eq!(prefix_a.cmp(&prefix_b), Ordering::Greater)
     }
+
+    #[test]
+    fn test_aggregate() {
+        let cidrs = [
+            "10.0.1.0/24",
+            "10.0.0.0/24",
+            "10.0.0.128/25",
+            "10.0.3.0/24",
+            "2001:db8::/33",
+            "2001:db8:8000::/33",
+        ]
+        .map(|cidr| CIDR::from_str(cidr).unwrap());
+
+        assert_eq!(
+            aggregate(&cidrs)
+                .iter()
+                .map(|cidr| cidr.to_string())
+                .collect::<Vec<_>>(),
+            vec!["10.0.0.0/23", "10.0.3.0/24", "2001:db8::/32"]
+        );
+        assert_eq!(address_count(&cidrs), (768, 1 << 96));
+
+        let everything = [CIDR::from_str("::/0").unwrap()];
+        assert_eq!(aggregate(&everything), everything);
+        assert_eq!(address_count(&everything).1, u128::MAX);
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+        assert_eq!(format_date(19782), "2024-02-29");
+        assert_eq!(format_date(-1), "1969-12-31");
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
 }
@@ -0,0 +1,295 @@
+// Whois frontend (RFC 3912) over the same providers as the API
+//
+// The query is a single line with an address, a CIDR or an AS number (AS13335), the answer is sent
+// back as "key: value" lines with "%" comments and the connection is closed.
+
+use std::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use mtilib::types::{AddressResponse, RoutedResponse};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+};
+use tracing::{error, info};
+
+use crate::{
+    providers::{PrefixFilter, Providers},
+    settings::Settings,
+    utils::CIDR,
+};
+
+// Longer queries are cut off, none of the valid ones come close
+const MAX_QUERY_LENGTH: u64 = 1024;
+
+const HEADER: &str = concat!(
+    "% Diglett whois server\n",
+    "% The data come from the public registry, routing and geolocation sources\n",
+);
+
+fn attribute(response: &mut String, key: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(response, "{:<16}{}", concat_string!(key, ":"), value);
+}
+
+fn address_attributes(response: &mut String, address: &AddressResponse, routed: &RoutedResponse) {
+    attribute(response, "status", address.allocation_state.id());
+    if let Some(special_purpose) = &address.special_purpose {
+        attribute(response, "special-purpose", &special_purpose.name);
+        attribute(response, "special-prefix", &special_purpose.prefix);
+        attribute(response, "special-rfc", &special_purpose.rfc);
+    }
+    if let Some(top_rir) = &address.top_rir {
+        attribute(response, "top-rir", top_rir);
+    }
+    if let Some(rir) = &address.rir {
+        attribute(response, "rir", rir);
+    }
+    if let Some(country) = &address.country {
+        attribute(response, "country", country);
+    }
+    if let Some(registration_date) = &address.registration_date {
+        attribute(response, "registered", registration_date);
+    }
+    if let Some(opaque_id) = &address.opaque_id {
+        attribute(response, "holder", opaque_id);
+    }
+
+    if let Some(prefix) = &routed.prefix {
+        attribute(response, "route", prefix);
+    }
+    for origin in &routed.origins {
+        attribute(response, "origin", concat_string!("AS", origin.to_string()));
+    }
+    if let Some(rpki) = &address.rpki {
+        attribute(response, "rpki", rpki.id());
+    }
+
+    if let Some(geo) = &address.geo {
+        if let Some(country) = &geo.country {
+            attribute(response, "geo-country", country);
+        }
+        if let Some(city) = &geo.city {
+            attribute(response, "geo-city", city);
+        }
+        if let (Some(latitude), Some(longitude)) = (geo.latitude, geo.longitude) {
+            attribute(
+                response,
+                "geo-location",
+                format!("{}, {}", latitude, longitude),
+            );
+        }
+    }
+}
+
+fn query_address(providers: &Providers, response: &mut String, address: IpAddr) {
+    let _ = writeln!(response, "% Information related to {}\n", address);
+    attribute(response, "address", address);
+    address_attributes(
+        response,
+        &providers.address(address),
+        &providers.routed(address),
+    );
+}
+
+// The block is described by its network address, the same way as the RIR stats describe it
+fn query_cidr(providers: &Providers, response: &mut String, cidr: CIDR) {
+    let first_address = cidr.first_address();
+    let _ = writeln!(
+        response,
+        "% Information related to {}/{}\n",
+        first_address, cidr.mask
+    );
+    attribute(
+        response,
+        "inetnum",
+        format!("{} - {}", first_address, cidr.last_address()),
+    );
+    address_attributes(
+        response,
+        &providers.address(first_address),
+        &providers.routed(first_address),
+    );
+}
+
+fn query_autsys(providers: &Providers, response: &mut String, asn: u32) {
+    let autsys = match providers.autsys(asn) {
+        Some(autsys) => autsys,
+        None => {
+            let _ = writeln!(response, "%ERROR:101: no entries found for AS{}", asn);
+            return;
+        }
+    };
+
+    let _ = writeln!(response, "% Information related to AS{}\n", asn);
+    attribute(response, "aut-num", concat_string!("AS", asn.to_string()));
+    if let Some(name) = providers.autsys_name(asn) {
+        attribute(response, "as-name", name.name);
+    }
+    attribute(response, "status", autsys.allocation_state.id());
+    attribute(response, "rir", &autsys.rir);
+    if let Some(country) = &autsys.country {
+        attribute(response, "country", country);
+    }
+    if let Some(registration_date) = &autsys.registration_date {
+        attribute(response, "registered", registration_date);
+    }
+    if let Some(opaque_id) = &autsys.opaque_id {
+        attribute(response, "holder", opaque_id);
+    }
+
+    // Only the summary, the full list is available at /asn/{asn}/prefixes
+    if let Some(prefixes) = providers.prefixes(&PrefixFilter::Asn(asn), true) {
+        attribute(response, "routes", prefixes.prefixes.len());
+        attribute(response, "ipv4-addresses", prefixes.ipv4_addresses);
+        attribute(response, "ipv6-addresses", prefixes.ipv6_addresses);
+    }
+}
+
+/// Answers a single whois query, the response always ends with an empty line.
+pub fn query(providers: &Providers, query: &str) -> String {
+    let query = query.trim();
+    let mut response = String::from(HEADER);
+    response.push('\n');
+
+    if let Ok(address) = IpAddr::from_str(query) {
+        query_address(providers, &mut response, address);
+    } else if let Ok(cidr) = CIDR::from_str(query) {
+        query_cidr(providers, &mut response, cidr);
+    } else if let Some(asn) = query
+        .get(..2)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("as"))
+        .and_then(|_| query[2..].parse::<u32>().ok())
+    {
+        query_autsys(providers, &mut response, asn);
+    } else {
+        let _ = writeln!(
+            response,
+            "%ERROR:100: invalid query, expected an address, a CIDR or an AS number (AS<n>)"
+        );
+    }
+
+    response.push('\n');
+    response
+}
+
+async fn handle(
+    stream: TcpStream,
+    providers: Arc<RwLock<Providers>>,
+    timeout: Duration,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = String::new();
+    let mut reader = BufReader::new(reader.take(MAX_QUERY_LENGTH));
+    match tokio::time::timeout(timeout, reader.read_line(&mut line)).await {
+        Ok(result) => result?,
+        Err(_) => return Ok(()),
+    };
+
+    let response = query(&*providers.read().await, &line);
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+pub async fn serve(listener: TcpListener, providers: Arc<RwLock<Providers>>, timeout: Duration) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                error!("Failed to accept a whois connection! ({})", error);
+                continue;
+            }
+        };
+
+        let providers = providers.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle(stream, providers, timeout).await {
+                error!(
+                    "Failed to answer the whois query from {}! ({})",
+                    peer, error
+                );
+            }
+        });
+    }
+}
+
+pub async fn run(settings: Arc<Settings>, providers: Arc<RwLock<Providers>>) {
+    let whois = settings
+        .whois
+        .as_ref()
+        .expect("Invalid config (whois section is missing)!");
+
+    let listener = TcpListener::bind(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        whois.port,
+    ))
+    .await
+    .unwrap();
+
+    info!("Whois listening on port {}!", whois.port);
+    serve(listener, providers, Duration::from_secs(whois.timeout)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::RwLock,
+    };
+
+    use super::{query, serve};
+    use crate::providers::Providers;
+
+    #[tokio::test]
+    async fn test_query() {
+        let providers = Providers::fixture().await;
+
+        let response = query(&providers, "1.1.1.1\r\n");
+        assert!(response.contains("address:        1.1.1.1\n"));
+        assert!(response.contains("status:         allocated\n"));
+        assert!(response.contains("rir:            APNIC\n"));
+        assert!(response.contains("country:        AU\n"));
+        assert!(response.contains("route:          1.1.1.0/24\n"));
+        assert!(response.contains("origin:         AS13335\n"));
+
+        let response = query(&providers, "1.1.1.7/24");
+        assert!(response.contains("inetnum:        1.1.1.0 - 1.1.1.255\n"));
+        assert!(response.contains("registered:     2011-08-11\n"));
+
+        let response = query(&providers, "as13335");
+        assert!(response.contains("aut-num:        AS13335\n"));
+        assert!(response.contains("rir:            ARIN\n"));
+        assert!(response.contains("routes:         1\n"));
+        assert!(response.contains("ipv4-addresses: 256\n"));
+
+        assert!(query(&providers, "AS64496").contains("%ERROR:101:"));
+        assert!(query(&providers, "example.com").contains("%ERROR:100:"));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(RwLock::new(Providers::fixture().await)),
+            Duration::from_secs(5),
+        ));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"AS13335\r\n").await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("% Diglett whois server\n"));
+        assert!(response.contains("aut-num:        AS13335\n"));
+    }
+}
@@ -7,6 +7,14 @@ CREATE TABLE "public"."AddressAllocationStates" (
     CONSTRAINT "AddressAllocationStates_pkey" PRIMARY KEY ("id")
 ) WITH (oids = false);
 
+INSERT INTO "AddressAllocationStates" ("id", "name") VALUES
+('unknown',	'Unknown'),
+('reserved',	'Reserved'),
+('unallocated',	'Unallocated'),
+('allocated',	'Allocated'),
+('multicast',	'Multicast'),
+('bogon',	'Bogon');
+
 
 DROP TABLE IF EXISTS "AddressMaps";
 CREATE TABLE "public"."AddressMaps" (
@@ -30,6 +38,11 @@ CREATE TABLE "public"."Addresses" (
     "rir_id" character varying(16),
     "autsys_id" bigint,
     "country" character varying(3),
+    "rpki_state" character varying(16),
+    "geo_country" character varying(3),
+    "geo_city" character varying(255),
+    "latitude" double precision,
+    "longitude" double precision,
     "updated_at" timestamptz DEFAULT now() NOT NULL,
     CONSTRAINT "Addresses_id" PRIMARY KEY ("id")
 ) WITH (oids = false);
@@ -38,6 +51,8 @@ CREATE TABLE "public"."Addresses" (
 DROP TABLE IF EXISTS "Autsyses";
 CREATE TABLE "public"."Autsyses" (
     "id" bigint NOT NULL,
+    "name" character varying(255),
+    "country" character varying(2),
     CONSTRAINT "Asns_id" PRIMARY KEY ("id")
 ) WITH (oids = false);
 
@@ -74,4 +89,9 @@ ALTER TABLE ONLY "public"."Addresses" ADD CONSTRAINT "Addresses_asn_id_fkey" FOR
 ALTER TABLE ONLY "public"."Addresses" ADD CONSTRAINT "Addresses_rir_id_fkey" FOREIGN KEY (rir_id) REFERENCES "Rirs"(id) NOT DEFERRABLE;
 ALTER TABLE ONLY "public"."Addresses" ADD CONSTRAINT "Addresses_top_rir_id_fkey" FOREIGN KEY (top_rir_id) REFERENCES "Rirs"(id) NOT DEFERRABLE;
 
+-- Databases created before the RPKI and GeoIP columns and the multicast and bogon allocation states were added need them added by hand:
+-- ALTER TABLE "Addresses" ADD COLUMN "rpki_state" character varying(16), ADD COLUMN "geo_country" character varying(3), ADD COLUMN "geo_city" character varying(255), ADD COLUMN "latitude" double precision, ADD COLUMN "longitude" double precision;
+-- INSERT INTO "AddressAllocationStates" ("id", "name") VALUES ('multicast', 'Multicast'), ('bogon', 'Bogon') ON CONFLICT DO NOTHING;
+-- The special-purpose registry details are kept in the existing "allocation_state_comment" column, it doesn't need a migration.
+
 -- 2025-01-17 21:39:27.487874+01
@@ -16,6 +16,11 @@ pub struct Address {
     pub rir_id: Option<String>,
     pub autsys_id: Option<i64>,
     pub country: Option<String>,
+    pub rpki_state: Option<String>,
+    pub geo_country: Option<String>,
+    pub geo_city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -31,6 +36,11 @@ pub struct NewAddress {
     pub rir_id: Option<String>,
     pub autsys_id: Option<i64>,
     pub country: Option<String>,
+    pub rpki_state: Option<String>,
+    pub geo_country: Option<String>,
+    pub geo_city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -47,6 +57,8 @@ pub struct AddressMap {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Autsys {
     pub id: i64,
+    pub name: Option<String>,
+    pub country: Option<String>,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -0,0 +1,682 @@
+// Client of the Diglett API
+//
+// The requests go to the unit which answered last. Connection errors, timeouts, 5xx and 429 responses
+// fail over to the other units and, once all of them have been tried, the round is retried after an
+// exponential backoff. The units are either given up front or discovered from Pokedex, in which case
+// they're re-discovered in the background for as long as the client lives, the configured address
+// always being tried first. The token is re-read from Pokedex on every re-discovery and, when a unit
+// answers with 401, refreshed and the request resent once.
+
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use concat_string::concat_string;
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::{
+    db::models::ServiceUnit,
+    pokedex::Pokedex,
+    types::{
+        AddressResponse, AllocationResponse, AllocationState, AsnNameResponse, AsnResponse,
+        ChangesResponse, LookupRequest, LookupResponse, Rir, ValueResponse,
+    },
+};
+
+const DIGLETT_SERVICE_ID: &str = "diglett";
+const DEFAULT_ATTEMPTS: u32 = 3;
+const BACKOFF: Duration = Duration::from_millis(250);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Most addresses Diglett looks up in a single request
+pub const LOOKUP_BATCH_SIZE: usize = 65536;
+const LOOKUP_CONCURRENCY: usize = 4;
+
+#[derive(Debug)]
+pub enum DiglettError {
+    /// No Diglett unit is configured or registered to Pokedex
+    NoUnits,
+    /// The request couldn't be sent or its response couldn't be read
+    Request(reqwest::Error),
+    Status(StatusCode),
+    /// The response doesn't hold the expected value
+    Decode(String),
+}
+
+impl DiglettError {
+    /// Whether another unit (or the same one a bit later) may answer the request.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DiglettError::NoUnits => false,
+            DiglettError::Request(error) => !error.is_builder() && !error.is_decode(),
+            DiglettError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            DiglettError::Decode(_) => false,
+        }
+    }
+
+    /// Status to answer with in front of Diglett, its own status when it answered with an error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DiglettError::Status(status) => *status,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for DiglettError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiglettError::NoUnits => write!(f, "no diglett units"),
+            DiglettError::Request(error) => write!(f, "request error: {}", error),
+            DiglettError::Status(status) => write!(f, "status {}", status),
+            DiglettError::Decode(error) => write!(f, "unexpected response: {}", error),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DiglettError {
+    fn from(error: reqwest::Error) -> Self {
+        DiglettError::Request(error)
+    }
+}
+
+// The paths are appended to the base urls, so they have to end with a slash
+fn base_url(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        url.set_path(&concat_string!(url.path(), "/"));
+    }
+    url
+}
+
+fn unit_url(unit: &ServiceUnit) -> Option<Url> {
+    let mut url = match Url::parse(&unit.address) {
+        Ok(url) => url,
+        Err(_) => {
+            error!("Failed to parse diglett unit address {}!", unit.address);
+            return None;
+        }
+    };
+    if let Some(port) = unit.port {
+        url.set_port(Some(port as u16)).ok()?;
+    }
+
+    Some(base_url(url))
+}
+
+async fn discover_units(
+    pokedex: &Mutex<Pokedex>,
+    address: Option<&Url>,
+) -> Result<Vec<Url>, reqwest::Error> {
+    let units = pokedex
+        .lock()
+        .await
+        .try_get_service_units(DIGLETT_SERVICE_ID)
+        .await?;
+
+    let mut urls = address.cloned().into_iter().collect::<Vec<_>>();
+    for url in units.iter().filter_map(unit_url) {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    Ok(urls)
+}
+
+// Treats the missing resources as a valid answer
+fn optional<T>(result: Result<T, DiglettError>) -> Result<Option<T>, DiglettError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(DiglettError::Status(StatusCode::NOT_FOUND)) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+pub struct Diglett {
+    client: reqwest::Client,
+    units: Arc<RwLock<Vec<Url>>>,
+    /// Index of the unit which answered last
+    preferred: AtomicUsize,
+    /// Bearer token sent with the requests, for the units with auth enabled
+    token: Arc<RwLock<Option<String>>>,
+    /// Source of the token, when the units are discovered
+    pokedex: Option<Arc<Mutex<Pokedex>>>,
+    attempts: u32,
+}
+
+impl Diglett {
+    /// Client of the units at the addresses, they're never re-discovered.
+    pub fn new(addresses: Vec<Url>) -> Self {
+        Diglett {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap(),
+            units: Arc::new(RwLock::new(addresses.into_iter().map(base_url).collect())),
+            preferred: AtomicUsize::new(0),
+            token: Arc::new(RwLock::new(None)),
+            pokedex: None,
+            attempts: DEFAULT_ATTEMPTS,
+        }
+    }
+
+    /// Client of the address (if any) and the Diglett units registered to Pokedex, which are re-discovered
+    /// every interval. The Pokedex token is used as the bearer token.
+    pub async fn discover(
+        address: Option<Url>,
+        pokedex: Arc<Mutex<Pokedex>>,
+        interval: Duration,
+    ) -> Self {
+        let address = address.map(base_url);
+        let units = match discover_units(&pokedex, address.as_ref()).await {
+            Ok(units) => units,
+            Err(error) => {
+                error!("Failed to discover diglett units! ({})", error);
+                address.iter().cloned().collect()
+            }
+        };
+        match units.len() {
+            0 => warn!(
+                "No diglett units found, retrying in {}s!",
+                interval.as_secs()
+            ),
+            count => info!("Found {} diglett units!", count),
+        }
+
+        let token = pokedex.lock().await.get_token();
+        let mut diglett = Diglett::new(units).with_token(token);
+        diglett.pokedex = Some(pokedex.clone());
+
+        // The task only holds weak references, so it ends together with the client
+        let weak_units = Arc::downgrade(&diglett.units);
+        let weak_token = Arc::downgrade(&diglett.token);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let (Some(units), Some(token)) = (weak_units.upgrade(), weak_token.upgrade())
+                else {
+                    break;
+                };
+
+                let mut result = discover_units(&pokedex, address.as_ref()).await;
+                if result
+                    .as_ref()
+                    .is_err_and(|error| error.status() == Some(StatusCode::UNAUTHORIZED))
+                {
+                    match pokedex.lock().await.refresh_token().await {
+                        Ok(_) => result = discover_units(&pokedex, address.as_ref()).await,
+                        Err(error) => error!("Failed to refresh the Pokedex token! ({})", error),
+                    }
+                }
+                // Another user of Pokedex may have refreshed it too
+                *token.write().await = Some(pokedex.lock().await.get_token());
+
+                match result {
+                    // Keep using the old units rather than none at all
+                    Ok(discovered) if discovered.is_empty() => {
+                        warn!("No diglett units found, keeping the previous ones!")
+                    }
+                    Ok(discovered) => {
+                        let mut units = units.write().await;
+                        if *units != discovered {
+                            info!("Found {} diglett units!", discovered.len());
+                            *units = discovered;
+                        }
+                    }
+                    Err(error) => error!("Failed to re-discover diglett units! ({})", error),
+                }
+            }
+        });
+
+        diglett
+    }
+
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Arc::new(RwLock::new(Some(token)));
+        self
+    }
+
+    /// Rounds over all of the units before giving up, at least one.
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub async fn units(&self) -> Vec<Url> {
+        self.units.read().await.clone()
+    }
+
+    async fn send(
+        &self,
+        path: &str,
+        request: impl Fn(&reqwest::Client, String) -> RequestBuilder,
+    ) -> Result<reqwest::Response, DiglettError> {
+        let mut last_error = DiglettError::NoUnits;
+        let mut refreshed = false;
+
+        for attempt in 0..self.attempts {
+            if attempt > 0 {
+                tokio::time::sleep(BACKOFF * 2u32.pow(attempt - 1)).await;
+            }
+
+            // The units may get re-discovered in the meantime
+            let units = self.units.read().await.clone();
+            let start = self.preferred.load(Ordering::Relaxed);
+            for i in 0..units.len() {
+                let index = (start + i) % units.len();
+
+                let token = self.token.read().await.clone();
+                let mut result = self
+                    .send_to(&units[index], path, &request, token.as_deref())
+                    .await;
+                // The token may have expired, so it's refreshed and the request resent, only once
+                if !refreshed
+                    && matches!(result, Err(DiglettError::Status(StatusCode::UNAUTHORIZED)))
+                {
+                    refreshed = true;
+                    if let Some(token) = self.refresh_token(token).await {
+                        result = self
+                            .send_to(&units[index], path, &request, Some(&token))
+                            .await;
+                    }
+                }
+
+                match result {
+                    Ok(response) => {
+                        self.preferred.store(index, Ordering::Relaxed);
+                        return Ok(response);
+                    }
+                    Err(error) if error.is_transient() => {
+                        debug!(
+                            "Diglett unit {} failed, trying another! ({})",
+                            units[index], error
+                        );
+                        last_error = error;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn send_to(
+        &self,
+        unit: &Url,
+        path: &str,
+        request: &impl Fn(&reqwest::Client, String) -> RequestBuilder,
+        token: Option<&str>,
+    ) -> Result<reqwest::Response, DiglettError> {
+        let mut builder = request(&self.client, concat_string!(unit.as_str(), path));
+        if let Some(token) = token {
+            builder = builder.bearer_auth(token);
+        }
+
+        match builder.send().await {
+            Ok(response) if response.status().is_success() => Ok(response),
+            Ok(response) => Err(DiglettError::Status(response.status())),
+            Err(error) => Err(DiglettError::Request(error)),
+        }
+    }
+
+    /// New token from Pokedex, in place of the rejected one. `None` when it can't be refreshed.
+    async fn refresh_token(&self, rejected: Option<String>) -> Option<String> {
+        let mut pokedex = self.pokedex.as_ref()?.lock().await;
+
+        // Another request may have refreshed it in the meantime
+        let current = pokedex.get_token();
+        let token = if rejected.as_ref() != Some(&current) {
+            current
+        } else {
+            match pokedex.refresh_token().await {
+                Ok(token) => {
+                    info!("Refreshed the Pokedex token!");
+                    token
+                }
+                Err(error) => {
+                    error!("Failed to refresh the Pokedex token! ({})", error);
+                    return None;
+                }
+            }
+        };
+
+        *self.token.write().await = Some(token.clone());
+        Some(token)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, DiglettError> {
+        Ok(self
+            .send(path, |client, url| client.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn address(
+        &self,
+        address: impl Into<IpAddr>,
+    ) -> Result<AddressResponse, DiglettError> {
+        self.get(&address.into().to_string()).await
+    }
+
+    pub async fn allocation_state(
+        &self,
+        address: impl Into<IpAddr>,
+    ) -> Result<AllocationState, DiglettError> {
+        let address = address.into().to_string();
+        let response: AllocationResponse =
+            self.get(&concat_string!(address, "/allocation")).await?;
+        AllocationState::from_str(&response.value)
+            .map_err(|error| DiglettError::Decode(format!("{:?}", error)))
+    }
+
+    /// The delegating RIR or, with `top`, the one IANA allocated the block to.
+    pub async fn rir(
+        &self,
+        address: impl Into<IpAddr>,
+        top: bool,
+    ) -> Result<Option<Rir>, DiglettError> {
+        let address = address.into().to_string();
+        let mut path = concat_string!(address, "/rir");
+        if top {
+            path = concat_string!(path, "?top=true");
+        }
+
+        let response: ValueResponse<Option<String>> = self.get(&path).await?;
+        response
+            .value
+            .map(|value| {
+                Rir::from_str(&value).map_err(|error| DiglettError::Decode(format!("{:?}", error)))
+            })
+            .transpose()
+    }
+
+    pub async fn asn(&self, address: impl Into<IpAddr>) -> Result<Option<u32>, DiglettError> {
+        let address = address.into().to_string();
+        let response: ValueResponse<Option<u32>> =
+            self.get(&concat_string!(address, "/asn")).await?;
+        Ok(response.value)
+    }
+
+    pub async fn country(
+        &self,
+        address: impl Into<IpAddr>,
+    ) -> Result<Option<String>, DiglettError> {
+        let address = address.into().to_string();
+        let response: ValueResponse<Option<String>> =
+            self.get(&concat_string!(address, "/country")).await?;
+        Ok(response.value)
+    }
+
+    /// Registry details of the AS number, `None` when Diglett doesn't know it.
+    pub async fn autsys(&self, asn: u32) -> Result<Option<AsnResponse>, DiglettError> {
+        let asn = asn.to_string();
+        optional(self.get(&concat_string!("asn/", asn)).await)
+    }
+
+    /// Name of the organisation using the AS number, `None` when Diglett doesn't know it.
+    pub async fn autsys_name(&self, asn: u32) -> Result<Option<AsnNameResponse>, DiglettError> {
+        let asn = asn.to_string();
+        optional(self.get(&concat_string!("asn/", asn, "/name")).await)
+    }
+
+    /// Prefixes whose registry or routing data changed since the `YYYY-MM-DD` date.
+    pub async fn changes(&self, since: &str) -> Result<ChangesResponse, DiglettError> {
+        self.get(&concat_string!("changes?since=", since)).await
+    }
+
+    /// A single lookup request, at most `LOOKUP_BATCH_SIZE` addresses.
+    pub async fn lookup(
+        &self,
+        request: &LookupRequest,
+    ) -> Result<Vec<LookupResponse>, DiglettError> {
+        Ok(self
+            .send("lookup", |client, url| client.post(url).json(request))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Looks up any number of addresses in batches, the responses are in the order of the addresses.
+    pub async fn lookup_addresses(
+        &self,
+        addresses: &[IpAddr],
+    ) -> Result<Vec<LookupResponse>, DiglettError> {
+        // Collected first, so that the stream doesn't borrow the addresses and stays Send for spawned tasks
+        let requests = addresses
+            .chunks(LOOKUP_BATCH_SIZE)
+            .map(|batch| LookupRequest::Addresses(batch.to_vec()))
+            .collect::<Vec<_>>();
+        let batches: Vec<Vec<LookupResponse>> = stream::iter(requests)
+            .map(|request| async move { self.lookup(&request).await })
+            .buffered(LOOKUP_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        Ok(batches.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde_json::json;
+    use tokio::{net::TcpListener, sync::Mutex};
+    use url::Url;
+
+    use super::{Diglett, DiglettError};
+    use crate::pokedex::Pokedex;
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+
+    async fn serve(app: Router) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Url::parse(&format!("http://{}/", address)).unwrap()
+    }
+
+    // Unit answering every request with the status, counting the requests
+    async fn unit(status: StatusCode, hits: Arc<AtomicUsize>) -> Url {
+        serve(Router::new().fallback(move || async move {
+            hits.fetch_add(1, Ordering::Relaxed);
+            (status, Json(json!({ "value": 13335 })))
+        }))
+        .await
+    }
+
+    // Pokedex handing out a new token on every login and the units in the list
+    async fn pokedex(logins: Arc<AtomicUsize>, units: Arc<Mutex<Vec<String>>>) -> Url {
+        let app = Router::new()
+            .route(
+                "/v2/auth/login",
+                post(move || async move {
+                    format!("token-{}", logins.fetch_add(1, Ordering::Relaxed) + 1)
+                }),
+            )
+            .route(
+                "/v2/services/diglett/units",
+                get(move || async move {
+                    let units = units.lock().await;
+                    Json(
+                        units
+                            .iter()
+                            .map(|address| {
+                                json!({
+                                    "id": "00000000-0000-0000-0000-000000000000",
+                                    "service_id": "diglett",
+                                    "address": address,
+                                    "port": null,
+                                    "created_at": "2025-01-01T00:00:00Z",
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }),
+            );
+
+        serve(app).await
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        // Nothing listens on the address once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+
+        let failing_hits = Arc::new(AtomicUsize::new(0));
+        let failing = unit(StatusCode::INTERNAL_SERVER_ERROR, failing_hits.clone()).await;
+        let working = unit(StatusCode::OK, Arc::new(AtomicUsize::new(0))).await;
+
+        let diglett = Diglett::new(vec![closed, failing, working]);
+        assert_eq!(diglett.asn(ADDRESS).await.unwrap(), Some(13335));
+        assert_eq!(failing_hits.load(Ordering::Relaxed), 1);
+
+        // The unit which answered is asked first from then on
+        assert_eq!(diglett.asn(ADDRESS).await.unwrap(), Some(13335));
+        assert_eq!(failing_hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_non_transient_error() {
+        let working_hits = Arc::new(AtomicUsize::new(0));
+        let missing = unit(StatusCode::NOT_FOUND, Arc::new(AtomicUsize::new(0))).await;
+        let working = unit(StatusCode::OK, working_hits.clone()).await;
+
+        let diglett = Diglett::new(vec![missing, working]);
+        assert!(matches!(
+            diglett.asn(ADDRESS).await,
+            Err(DiglettError::Status(StatusCode::NOT_FOUND))
+        ));
+        assert_eq!(working_hits.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let failing = unit(StatusCode::SERVICE_UNAVAILABLE, hits.clone()).await;
+
+        let diglett = Diglett::new(vec![failing]).with_attempts(3);
+        let start = Instant::now();
+        assert!(matches!(
+            diglett.asn(ADDRESS).await,
+            Err(DiglettError::Status(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+        // Backing off 250ms and then 500ms
+        assert!(start.elapsed() >= Duration::from_millis(750));
+
+        assert!(matches!(
+            Diglett::new(Vec::new()).asn(ADDRESS).await,
+            Err(DiglettError::NoUnits)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rediscovery() {
+        let units = Arc::new(Mutex::new(vec!["http://127.0.0.1:8001".to_string()]));
+        let address = pokedex(Arc::new(AtomicUsize::new(0)), units.clone()).await;
+        let pokedex = Arc::new(Mutex::new(
+            Pokedex::login(&address, "user", "password").await.unwrap(),
+        ));
+
+        let configured = Url::parse("http://127.0.0.1:8000").unwrap();
+        let diglett = Diglett::discover(
+            Some(configured.clone()),
+            pokedex.clone(),
+            Duration::from_millis(50),
+        )
+        .await;
+        assert_eq!(
+            diglett.units().await,
+            vec![
+                configured.clone(),
+                Url::parse("http://127.0.0.1:8001/").unwrap()
+            ]
+        );
+
+        *units.lock().await = vec![
+            "http://127.0.0.1:8002".to_string(),
+            "http://127.0.0.1:8003".to_string(),
+        ];
+        // The token refreshed elsewhere gets picked up as well
+        pokedex.lock().await.refresh_token().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(
+            diglett.units().await,
+            vec![
+                configured,
+                Url::parse("http://127.0.0.1:8002/").unwrap(),
+                Url::parse("http://127.0.0.1:8003/").unwrap()
+            ]
+        );
+        assert_eq!(diglett.token.read().await.as_deref(), Some("token-2"));
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh() {
+        let logins = Arc::new(AtomicUsize::new(0));
+        let address = pokedex(logins.clone(), Arc::new(Mutex::new(Vec::new()))).await;
+        let pokedex = Arc::new(Mutex::new(
+            Pokedex::login(&address, "user", "password").await.unwrap(),
+        ));
+
+        // Accepts only the token of the second login
+        let authorized = serve(Router::new().fallback(|headers: HeaderMap| async move {
+            match headers.get(header::AUTHORIZATION) {
+                Some(token) if token == "Bearer token-2" => {
+                    Json(json!({ "value": 13335 })).into_response()
+                }
+                _ => StatusCode::UNAUTHORIZED.into_response(),
+            }
+        }))
+        .await;
+
+        let diglett = Diglett::discover(Some(authorized), pokedex, Duration::from_secs(3600)).await;
+        assert_eq!(diglett.asn(ADDRESS).await.unwrap(), Some(13335));
+        assert_eq!(logins.load(Ordering::Relaxed), 2);
+        assert_eq!(diglett.token.read().await.as_deref(), Some("token-2"));
+
+        // The request is resent only once
+        let rejecting_hits = Arc::new(AtomicUsize::new(0));
+        let rejecting = unit(StatusCode::UNAUTHORIZED, rejecting_hits.clone()).await;
+        *diglett.units.write().await = vec![rejecting];
+        assert!(matches!(
+            diglett.asn(ADDRESS).await,
+            Err(DiglettError::Status(StatusCode::UNAUTHORIZED))
+        ));
+        assert_eq!(rejecting_hits.load(Ordering::Relaxed), 2);
+        assert_eq!(logins.load(Ordering::Relaxed), 3);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod auth;
 #[cfg(any(feature = "sqlx"))]
 pub mod db;
+#[cfg(feature = "diglett")]
+pub mod diglett;
 pub mod pidgey;
 #[cfg(feature = "pokedex")]
 pub mod pokedex;
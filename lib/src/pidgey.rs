@@ -3,7 +3,10 @@ use serde::{Deserialize, Serialize};
 use std::net::Ipv4Addr;
 use uuid::Uuid;
 
-use crate::types::{AllocationState, Rir};
+use crate::types::{
+    AllocationState, AsnNameResponse, GeoResponse, LookupResponse, Rir, RpkiState,
+    SpecialPurposeResponse,
+};
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -17,12 +20,29 @@ pub struct PidgeyCommand {
 pub enum PidgeyCommandPayload {
     Register,
     Deregister,
-    Query { address: Ipv4Addr },
-    AllocationState { address: Ipv4Addr },
-    Rir { address: Ipv4Addr, top: bool },
-    Autsys { address: Ipv4Addr },
-    Country { address: Ipv4Addr },
-    Online { address: Ipv4Addr },
+    Query {
+        address: Ipv4Addr,
+    },
+    AllocationState {
+        address: Ipv4Addr,
+    },
+    Rir {
+        address: Ipv4Addr,
+        top: bool,
+    },
+    Autsys {
+        address: Ipv4Addr,
+    },
+    Country {
+        address: Ipv4Addr,
+    },
+    Online {
+        address: Ipv4Addr,
+    },
+    /// Registry data of all of the addresses, looked up in a single Diglett request
+    Lookup {
+        addresses: Vec<Ipv4Addr>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -46,6 +66,16 @@ pub enum PidgeyCommandResponsePayload {
         autsys: Option<u32>,
         country: Option<String>,
         online: bool,
+        #[cfg_attr(feature = "serde", serde(default))]
+        rpki: Option<RpkiState>,
+        // The larger details are boxed to keep the other responses small
+        #[cfg_attr(feature = "serde", serde(default))]
+        geo: Option<Box<GeoResponse>>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        special_purpose: Option<Box<SpecialPurposeResponse>>,
+        /// Name of the organisation using the AS number, missing when Diglett doesn't know it
+        #[cfg_attr(feature = "serde", serde(default))]
+        autsys_name: Option<AsnNameResponse>,
     },
     AllocationState {
         value: AllocationState,
@@ -63,4 +93,11 @@ pub enum PidgeyCommandResponsePayload {
         value: bool,
         reason: Option<String>,
     },
+    Lookup {
+        values: Vec<LookupResponse>,
+    },
+    /// The command couldn't be answered, e.g. Diglett couldn't be reached
+    Failed {
+        reason: String,
+    },
 }
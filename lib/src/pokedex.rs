@@ -35,33 +35,55 @@ pub enum PokedexMessage {
 
 pub struct Pokedex {
     address: Url,
+    username: String,
+    password: String,
     token: String,
 }
 
+async fn login_request(
+    address: &Url,
+    username: &str,
+    password: &str,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let mut login_address = address.clone();
+    login_address.set_path("/v2/auth/login");
+
+    login_address
+        .query_pairs_mut()
+        .append_pair("username", username)
+        .append_pair("password", password);
+
+    let client = reqwest::Client::new();
+    client.post(login_address.clone()).send().await
+}
+
 impl Pokedex {
     pub async fn login<S: AsRef<str>>(
         address: &Url,
         username: S,
         password: S,
     ) -> Result<Self, reqwest::Error> {
-        let mut login_address = address.clone();
-        login_address.set_path("/v2/auth/login");
-
-        let mut query = login_address.query_pairs_mut();
-        query.append_pair("username", username.as_ref());
-        query.append_pair("password", password.as_ref());
-
-        drop(query);
-
-        let client = reqwest::Client::new();
-        let res = client.post(login_address.clone()).send().await?;
+        let res = login_request(address, username.as_ref(), password.as_ref()).await?;
 
         Ok(Pokedex {
             address: address.clone(),
+            username: username.as_ref().to_string(),
+            password: password.as_ref().to_string(),
             token: res.text().await.unwrap(),
         })
     }
 
+    /// Logs in again for a new token, e.g. once the current one expired. The token is kept on errors.
+    pub async fn refresh_token(&mut self) -> Result<String, reqwest::Error> {
+        self.token = login_request(&self.address, &self.username, &self.password)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(self.token.clone())
+    }
+
     // TODO: Proper error handling
     // TODO: Expose communication with Pokedex through WS
     // TODO: Make a separate client which handles communication with Pokedex ws
@@ -169,6 +191,24 @@ impl Pokedex {
         }
     }
 
+    /// Same as `get_service_units`, but returns the errors instead of panicking.
+    pub async fn try_get_service_units<S: AsRef<str>>(
+        &self,
+        service_id: S,
+    ) -> Result<Vec<ServiceUnit>, reqwest::Error> {
+        let mut service_units_url = self.address.clone();
+        service_units_url.set_path(&concat_string!("/v2/services/", service_id, "/units"));
+
+        reqwest::Client::new()
+            .get(service_units_url)
+            .bearer_auth(self.token.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
     // TODO: Split this into separate structs - one for db, one for Pokedex
     pub async fn get_service_units<S: AsRef<str>>(&self, service_id: S) -> Vec<ServiceUnit> {
         let mut service_units_url = self.address.clone();
@@ -1,13 +1,201 @@
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 pub mod allocation_state;
 pub mod rir;
+pub mod rpki_state;
 
 pub use allocation_state::AllocationState;
 pub use rir::Rir;
+pub use rpki_state::RpkiState;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ValueResponse<T> {
     pub value: T,
 }
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AddressResponse {
+    pub allocation_state: AllocationState,
+    pub top_rir: Option<Rir>,
+    pub rir: Option<Rir>,
+    pub asn: Option<u32>,
+    /// Name of the organisation using the AS number, missing when the AS names list doesn't have it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub asn_name: Option<AsnNameResponse>,
+    /// Country the address is registered in by its holder (RIR stats)
+    pub country: Option<String>,
+    /// Date of the registration in the `YYYY-MM-DD` format
+    pub registration_date: Option<String>,
+    /// Opaque ID of the holder the address is registered to, unique within the RIR
+    pub opaque_id: Option<String>,
+    /// Route origin validation state of the route, missing when no ROAs are loaded
+    pub rpki: Option<RpkiState>,
+    /// Where the address is used (GeoIP database), missing when no database is loaded
+    pub geo: Option<GeoResponse>,
+    /// The IANA special-purpose block the address is in, if any
+    pub special_purpose: Option<SpecialPurposeResponse>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AllocationResponse {
+    pub value: String,
+    pub special_purpose: Option<SpecialPurposeResponse>,
+}
+
+/// Entry of the IANA special-purpose address registries (RFC 6890), the flags are missing when not applicable
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SpecialPurposeResponse {
+    pub prefix: String,
+    pub name: String,
+    pub rfc: String,
+    pub source: Option<bool>,
+    pub destination: Option<bool>,
+    pub forwardable: Option<bool>,
+    pub globally_reachable: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GeoResponse {
+    /// ISO 3166-1 alpha-2 code of the country the address is located in
+    pub country: Option<String>,
+    /// English name of the city
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Radius in kilometers around the coordinates the address is likely to be within
+    pub accuracy_radius: Option<u16>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AsnResponse {
+    pub asn: u32,
+    pub allocation_state: AllocationState,
+    pub rir: Rir,
+    pub country: Option<String>,
+    /// Date of the registration in the `YYYY-MM-DD` format
+    pub registration_date: Option<String>,
+    pub opaque_id: Option<String>,
+}
+
+/// Name of the organisation using an AS number, as announced in the routing data
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AsnNameResponse {
+    pub asn: u32,
+    pub name: String,
+    /// Country of the organisation, missing when the list doesn't have it
+    pub country: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RoutedResponse {
+    pub routed: bool,
+    /// The most specific routed prefix containing the address
+    pub prefix: Option<String>,
+    /// Origin ASNs of the prefix, the one seen by the most peers first
+    pub origins: Vec<u32>,
+    /// Whether the prefix is originated by multiple ASNs
+    pub moas: bool,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RpkiResponse {
+    pub state: RpkiState,
+    /// The validated route, missing when the address isn't routed
+    pub prefix: Option<String>,
+    pub asn: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrefixesResponse {
+    pub prefixes: Vec<String>,
+    /// Number of IPv4 addresses covered by the prefixes, the overlaps are counted once
+    pub ipv4_addresses: u64,
+    /// Number of IPv6 addresses covered by the prefixes, the overlaps are counted once
+    pub ipv6_addresses: u128,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrefixBlock {
+    pub cidr: String,
+    pub allocation_state: AllocationState,
+    pub rir: Rir,
+    pub country: Option<String>,
+    pub registration_date: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AsnBlock {
+    pub start: u32,
+    pub count: u32,
+    pub allocation_state: AllocationState,
+    pub rir: Rir,
+    pub country: Option<String>,
+    pub registration_date: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HolderResponse {
+    pub opaque_id: String,
+    pub prefixes: Vec<PrefixBlock>,
+    pub asns: Vec<AsnBlock>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LookupRequest {
+    Addresses(Vec<IpAddr>),
+    Cidr(String),
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LookupResponse {
+    pub address: IpAddr,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub details: AddressResponse,
+}
+
+/// What the registry and routing data of a provider say about a prefix, missing when it doesn't have the prefix
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrefixState {
+    pub allocation_state: Option<AllocationState>,
+    pub rir: Option<Rir>,
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrefixChange {
+    pub prefix: String,
+    /// The provider whose data changed
+    pub provider: String,
+    pub before: PrefixState,
+    pub after: PrefixState,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChangesResponse {
+    /// Date of the oldest archive compared against in the `YYYY-MM-DD` format, missing when there is none
+    pub archived: Option<String>,
+    /// Providers without an archive from before the date, their changes can't be listed
+    pub missing: Vec<String>,
+    pub changes: Vec<PrefixChange>,
+}
@@ -17,6 +17,10 @@ pub enum AllocationState {
     Reserved,
     Unallocated,
     Allocated,
+    /// Multicast address space (224.0.0.0/4, ff00::/8)
+    Multicast,
+    /// Address space which shouldn't be routed, the full bogons also include the unallocated space
+    Bogon,
 }
 
 impl AllocationState {
@@ -26,6 +30,8 @@ impl AllocationState {
             AllocationState::Reserved => "reserved",
             AllocationState::Unallocated => "unallocated",
             AllocationState::Allocated => "allocated",
+            AllocationState::Multicast => "multicast",
+            AllocationState::Bogon => "bogon",
         }
     }
 }
@@ -37,6 +43,8 @@ impl Display for AllocationState {
             AllocationState::Reserved => "Reserved",
             AllocationState::Unallocated => "Unallocated",
             AllocationState::Allocated => "Allocated",
+            AllocationState::Multicast => "Multicast",
+            AllocationState::Bogon => "Bogon",
         })
     }
 }
@@ -53,9 +61,12 @@ impl FromStr for AllocationState {
         match s.to_lowercase().as_str() {
             "reserved" => Ok(AllocationState::Reserved),
             "available" => Ok(AllocationState::Unallocated),
+            "unallocated" => Ok(AllocationState::Unallocated),
             "allocated" => Ok(AllocationState::Allocated),
             "assigned" => Ok(AllocationState::Allocated), // Maybe not correct?
             "unknown" => Ok(AllocationState::Unknown),
+            "multicast" => Ok(AllocationState::Multicast),
+            "bogon" => Ok(AllocationState::Bogon),
             _ => Err(AllocStateParseErr::UnknownState(s.to_string())),
         }
     }
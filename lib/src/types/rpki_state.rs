@@ -0,0 +1,52 @@
+use std::{fmt::Display, str::FromStr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Route origin validation state (RFC 6811)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RpkiState {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+impl RpkiState {
+    pub fn id(&self) -> &str {
+        match self {
+            RpkiState::Valid => "valid",
+            RpkiState::Invalid => "invalid",
+            RpkiState::NotFound => "not_found",
+        }
+    }
+}
+
+impl Display for RpkiState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RpkiState::Valid => "Valid",
+            RpkiState::Invalid => "Invalid",
+            RpkiState::NotFound => "Not found",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RpkiStateParseErr {
+    UnknownState(String),
+}
+
+impl FromStr for RpkiState {
+    type Err = RpkiStateParseErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "valid" => Ok(RpkiState::Valid),
+            "invalid" => Ok(RpkiState::Invalid),
+            "not_found" | "notfound" | "unknown" => Ok(RpkiState::NotFound),
+            _ => Err(RpkiStateParseErr::UnknownState(s.to_string())),
+        }
+    }
+}
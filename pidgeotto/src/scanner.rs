@@ -3,18 +3,91 @@ use ipnetwork::{IpNetwork, Ipv4Network};
 use mtilib::db::models::NewAddress;
 use mtilib::db::DbPool;
 use mtilib::pidgey::{PidgeyCommand, PidgeyCommandPayload, PidgeyCommandResponsePayload};
+use mtilib::types::{AddressResponse, AllocationState, SpecialPurposeResponse};
 use sqlx::QueryBuilder;
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::Semaphore;
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
 use crate::pidgey::{Pidgey, PidgeyUnitRequest};
 use crate::settings::Settings;
 
+// Fits the special-purpose registry details into the allocation state comment column
+// e.g. "Private-Use [RFC1918] (source, destination, forwardable, not globally reachable)"
+fn allocation_state_comment(special_purpose: SpecialPurposeResponse) -> String {
+    let flags = [
+        ("source", special_purpose.source),
+        ("destination", special_purpose.destination),
+        ("forwardable", special_purpose.forwardable),
+        ("globally reachable", special_purpose.globally_reachable),
+    ]
+    .into_iter()
+    .filter_map(|(name, flag)| {
+        flag.map(|flag| match flag {
+            true => name.to_string(),
+            false => format!("not {}", name),
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let mut comment = format!("{} {}", special_purpose.name, special_purpose.rfc);
+    if !flags.is_empty() {
+        comment = format!("{} ({})", comment, flags.join(", "));
+    }
+
+    comment.chars().take(255).collect()
+}
+
+// Same as the response to the Query command, an address which is online must be allocated
+fn query_response(details: AddressResponse, online: bool) -> PidgeyCommandResponsePayload {
+    PidgeyCommandResponsePayload::Query {
+        allocation_state: match online {
+            true => AllocationState::Allocated,
+            false => details.allocation_state,
+        },
+        top_rir: details.top_rir,
+        rir: details.rir,
+        autsys: details.asn,
+        country: details.country,
+        online,
+        rpki: details.rpki,
+        geo: details.geo.map(Box::new),
+        special_purpose: details.special_purpose.map(Box::new),
+        autsys_name: details.asn_name,
+    }
+}
+
+// Sends the command to a random Pidgey unit, again when the unit goes away before answering
+async fn send_command(
+    pidgey: &Pidgey,
+    payload: PidgeyCommandPayload,
+) -> PidgeyCommandResponsePayload {
+    loop {
+        let unit = pidgey.get_unit().await;
+        let (job_tx, job_rx) = tokio::sync::oneshot::channel::<PidgeyCommandResponsePayload>();
+
+        unit.tx
+            .send(PidgeyUnitRequest {
+                command: PidgeyCommand {
+                    id: Uuid::new_v4(),
+                    payload: payload.clone(),
+                },
+                response: job_tx,
+            })
+            .await
+            .unwrap();
+
+        match job_rx.await {
+            Ok(response) => return response,
+            Err(_) => error!("Error while querying Pidgey unit {}, retrying...", unit.id),
+        }
+    }
+}
+
 // Main entry point of Pidgeotto
 pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>) {
     // Define the maximum number of tasks allowed to be active in parallel
@@ -66,72 +139,94 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
             .into_iter()
             .collect();
 
+        // Only the missing and stale records get scanned
+        let addresses = addresses_scanning
+            .into_iter()
+            .filter(|address| {
+                addresses_in_db.get(address).is_none_or(|updated_at| {
+                    (Utc::now() - updated_at).num_days() >= settings.scanner.stale
+                })
+            })
+            .filter_map(|address| match address.ip() {
+                IpAddr::V4(address) => Some(address),
+                IpAddr::V6(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        // The registry data of the whole batch comes from a single Diglett lookup, only the pings
+        // go out per address. A failed lookup leaves the batch missing, so that the next scan tries again
+        let lookups = match addresses.is_empty() {
+            true => Vec::new(),
+            false => {
+                match send_command(&pidgey, PidgeyCommandPayload::Lookup { addresses }).await {
+                    PidgeyCommandResponsePayload::Lookup { values } => values,
+                    PidgeyCommandResponsePayload::Failed { reason } => {
+                        error!(
+                            "Pidgey failed to look up the batch, skipping it! ({})",
+                            reason
+                        );
+                        Vec::new()
+                    }
+                    response => {
+                        error!("Unexpected lookup response from Pidgey! ({:?})", response);
+                        Vec::new()
+                    }
+                }
+            }
+        };
+
         let mut address_tasks = Vec::new();
-        let query_results = Arc::new(Mutex::new(HashMap::new()));
-
-        for address in addresses_scanning {
-            // Check if the record is missing or stale
-            if addresses_in_db.contains_key(&address)
-                && (Utc::now() - addresses_in_db.get(&address).unwrap()).num_days()
-                    < settings.scanner.stale
-            {
+        for lookup in lookups {
+            let IpAddr::V4(address) = lookup.address else {
                 continue;
-            }
+            };
 
             let cloned_task_permits = task_permits.clone();
             let cloned_pidgey = pidgey.clone();
-            let cloned_query_results = query_results.clone();
             address_tasks.push(tokio::spawn(async move {
-                loop {
-                    // Get permission to run
-                    let _permit = cloned_task_permits.acquire().await.unwrap();
-                    // Get a random Pidgey unit
-                    let unit = cloned_pidgey.get_unit().await;
-
-                    let (job_tx, job_rx) =
-                        tokio::sync::oneshot::channel::<PidgeyCommandResponsePayload>();
-
-                    let job_uuid = Uuid::new_v4();
-                    let ipaddr = match address.ip() {
-                        std::net::IpAddr::V4(ipv4_addr) => ipv4_addr,
-                        std::net::IpAddr::V6(_) => panic!("This should never happen"),
-                    };
-
-                    unit.tx
-                        .send(PidgeyUnitRequest {
-                            command: PidgeyCommand {
-                                id: job_uuid,
-                                payload: PidgeyCommandPayload::Query { address: ipaddr },
-                            },
-                            response: job_tx,
-                        })
-                        .await
-                        .unwrap();
-
-                    match job_rx.await {
-                        Ok(response) => {
-                            cloned_query_results.lock().await.insert(address, response);
-                            break;
-                        }
-                        Err(_) => {
-                            error!("Error while querying address {}, retrying...", address)
-                        }
-                    };
-                }
+                let online = match lookup.details.allocation_state {
+                    AllocationState::Reserved
+                    | AllocationState::Unallocated
+                    | AllocationState::Multicast
+                    | AllocationState::Bogon => false,
+                    _ => {
+                        // Get permission to run
+                        let _permit = cloned_task_permits.acquire().await.unwrap();
+                        matches!(
+                            send_command(&cloned_pidgey, PidgeyCommandPayload::Online { address })
+                                .await,
+                            PidgeyCommandResponsePayload::Online { value: true, .. }
+                        )
+                    }
+                };
+
+                (address, query_response(lookup.details, online))
             }));
         }
 
-        // Wait for all queries to finish
+        // Wait for all pings to finish
+        let mut query_results = HashMap::new();
         for task in address_tasks {
-            task.await.unwrap();
+            let (address, response) = task.await.unwrap();
+            query_results.insert(
+                IpNetwork::V4(Ipv4Network::new(address, 32).unwrap()),
+                response,
+            );
         }
 
-        let query_results = match Arc::try_unwrap(query_results) {
-            Ok(results) => results.into_inner(),
-            Err(_) => panic!("Failed to unwrap arc"),
-        };
-
         if !query_results.is_empty() {
+            // Names of the autsyses, only used when their records get created
+            let autsys_names = query_results
+                .values()
+                .filter_map(|x| match x {
+                    PidgeyCommandResponsePayload::Query {
+                        autsys_name: Some(autsys_name),
+                        ..
+                    } => Some((autsys_name.asn as i64, autsys_name.clone())),
+                    _ => None,
+                })
+                .collect::<HashMap<_, _>>();
+
             // Create new records
             let new_addresses = query_results
                 .into_iter()
@@ -143,11 +238,17 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
                         autsys,
                         country,
                         online,
+                        rpki,
+                        geo,
+                        special_purpose,
+                        ..
                     } => {
                         let top_rir_id = top_rir.map(|top_rir| top_rir.id().to_string());
 
                         let rir_id = rir.map(|rir| rir.id().to_string());
 
+                        let geo = geo.map(|geo| *geo).unwrap_or_default();
+
                         let mut routed = false;
                         let autsys_id = match autsys {
                             Some(autsys) => {
@@ -160,13 +261,19 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
                         NewAddress {
                             id: x.0,
                             allocation_state_id: allocation_state.id().to_string(),
-                            allocation_state_comment: None,
+                            allocation_state_comment: special_purpose
+                                .map(|special_purpose| allocation_state_comment(*special_purpose)),
                             top_rir_id,
                             rir_id,
                             autsys_id,
                             routed,
                             online,
                             country,
+                            rpki_state: rpki.map(|rpki| rpki.id().to_string()),
+                            geo_country: geo.country,
+                            geo_city: geo.city,
+                            latitude: geo.latitude,
+                            longitude: geo.longitude,
                         }
                     }
                     _ => panic!("Should not be here!"),
@@ -196,15 +303,32 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
                 .into_iter(),
             );
 
-            // Create missing autsyses
+            // Create missing autsyses, along with their names when Diglett knows them
+            let missing_autsyses = new_autsyses.difference(&autsyses_in_db).collect::<Vec<_>>();
             sqlx::query(
                 r#"
-                    INSERT INTO "Autsyses" (id)
-                    SELECT * FROM UNNEST($1)
+                    INSERT INTO "Autsyses" (id, name, country)
+                    SELECT * FROM UNNEST($1::bigint[], $2::varchar[], $3::varchar[])
                     RETURNING id
                     "#,
             )
-            .bind(new_autsyses.difference(&autsyses_in_db).collect::<Vec<_>>())
+            .bind(&missing_autsyses)
+            .bind(
+                missing_autsyses
+                    .iter()
+                    .map(|id| {
+                        autsys_names
+                            .get(id)
+                            .map(|x| x.name.chars().take(255).collect::<String>())
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .bind(
+                missing_autsyses
+                    .iter()
+                    .map(|id| autsys_names.get(id).and_then(|x| x.country.clone()))
+                    .collect::<Vec<_>>(),
+            )
             .execute(&mut *db_pool.acquire().await.unwrap())
             .await
             .unwrap();
@@ -237,7 +361,7 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
             // Create new address records
             // We can be sure that these are not duplicates because we checked that before
             let mut addresses_qb = QueryBuilder::new(
-                r#"INSERT INTO "Addresses" (id, allocation_state_id, allocation_state_comment, routed, online, top_rir_id, rir_id, autsys_id, country)"#,
+                r#"INSERT INTO "Addresses" (id, allocation_state_id, allocation_state_comment, routed, online, top_rir_id, rir_id, autsys_id, country, rpki_state, geo_country, geo_city, latitude, longitude)"#,
             );
 
             addresses_qb.push_values(new_addresses, |mut b, new_address| {
@@ -249,7 +373,12 @@ pub async fn run(settings: Arc<Settings>, db_pool: DbPool, pidgey: Arc<Pidgey>)
                     .push_bind(new_address.top_rir_id)
                     .push_bind(new_address.rir_id)
                     .push_bind(new_address.autsys_id)
-                    .push_bind(new_address.country);
+                    .push_bind(new_address.country)
+                    .push_bind(new_address.rpki_state)
+                    .push_bind(new_address.geo_country)
+                    .push_bind(new_address.geo_city)
+                    .push_bind(new_address.latitude)
+                    .push_bind(new_address.longitude);
             });
 
             let addresses_query = addresses_qb.build();
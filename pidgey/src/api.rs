@@ -1,7 +1,10 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
 use concat_string::concat_string;
 use config::Config;
-use mtilib::auth::{GetJWTKeys, JWTKeys};
+use mtilib::{
+    auth::{GetJWTKeys, JWTKeys},
+    diglett::Diglett,
+};
 use serde::Serialize;
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -12,8 +15,6 @@ use tower_http::trace::TraceLayer;
 use tracing::info;
 use uuid::Uuid;
 
-use crate::diglett::Diglett;
-
 #[derive(Serialize)]
 struct UnitResponse {
     uuid: Option<Uuid>,
@@ -43,7 +43,7 @@ pub async fn allocation(
         Ok(state) => Ok(Json(ValueResponse {
             value: state.id().to_string(),
         })),
-        Err(status) => Err(status),
+        Err(error) => Err(error.status()),
     }
 }
 
@@ -65,7 +65,7 @@ pub async fn rir(
             })),
             None => Ok(Json(ValueResponse { value: None })),
         },
-        Err(status) => Err(status),
+        Err(error) => Err(error.status()),
     }
 }
 
@@ -75,7 +75,7 @@ pub async fn asn(
 ) -> Result<Json<ValueResponse<Option<u32>>>, StatusCode> {
     match state.diglett.asn(address).await {
         Ok(asn) => Ok(Json(ValueResponse { value: asn })),
-        Err(status) => Err(status),
+        Err(error) => Err(error.status()),
     }
 }
 
@@ -85,7 +85,7 @@ pub async fn country(
 ) -> Result<Json<ValueResponse<Option<String>>>, StatusCode> {
     match state.diglett.country(address).await {
         Ok(country) => Ok(Json(ValueResponse { value: country })),
-        Err(status) => Err(status),
+        Err(error) => Err(error.status()),
     }
 }
 
@@ -1,7 +1,6 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use diglett::Diglett;
-use mtilib::{auth::JWTKeys, pokedex::Pokedex, Sprite};
+use mtilib::{auth::JWTKeys, diglett::Diglett, pokedex::Pokedex, Sprite};
 use settings::Settings;
 use tokio::{
     signal::{self, unix::SignalKind},
@@ -13,7 +12,6 @@ use url::Url;
 use uuid::Uuid;
 
 pub mod api;
-pub mod diglett;
 pub mod gust;
 pub mod pidgeotto;
 pub mod settings;
@@ -140,7 +138,16 @@ async fn main() {
     });
 
     // Diglett setup
-    let diglett = Arc::new(Diglett::new(settings.clone(), pokedex.clone()).await);
+    let diglett = Arc::new(
+        Diglett::discover(
+            settings.diglett.address.as_ref().map(|address| {
+                Url::parse(address).expect("Invalid config (diglett.address must be a valid url)!")
+            }),
+            pokedex.clone(),
+            Duration::from_secs(settings.diglett.rediscover),
+        )
+        .await,
+    );
 
     // Ping client setup
     let ping_client = Arc::new(surge_ping::Client::new(&surge_ping::Config::new()).unwrap());
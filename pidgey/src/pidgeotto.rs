@@ -1,14 +1,15 @@
 use axum::http::HeaderValue;
 use concat_string::concat_string;
 use futures::{SinkExt, StreamExt};
+use mtilib::diglett::{Diglett, DiglettError};
 use mtilib::pidgey::{
     PidgeyCommand, PidgeyCommandPayload, PidgeyCommandResponse, PidgeyCommandResponsePayload,
 };
 use mtilib::pokedex::Pokedex;
-use mtilib::types::AllocationState;
+use mtilib::types::{AddressResponse, AllocationState};
 use rand::random;
 use rand::seq::SliceRandom;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 use surge_ping::{PingIdentifier, PingSequence, SurgeError};
 use tokio::net::TcpStream;
@@ -20,9 +21,19 @@ use tracing::{debug, error, info};
 use url::Url;
 use uuid::Uuid;
 
-use crate::diglett::Diglett;
 use crate::settings::Settings;
 
+/// Answers a command Diglett couldn't serve, so that Pidgeotto doesn't wait on it
+fn failed(address: Ipv4Addr, error: DiglettError) -> PidgeyCommandResponsePayload {
+    error!(
+        "Failed to query Diglett for address {}! ({})",
+        address, error
+    );
+    PidgeyCommandResponsePayload::Failed {
+        reason: error.to_string(),
+    }
+}
+
 async fn try_connect(
     url: &Url,
     token: &String,
@@ -183,48 +194,35 @@ pub async fn run(
                         PidgeyCommandPayload::Query { address } => {
                             let _permit = cloned_worker_permits.acquire().await.unwrap();
 
-                            let alloc_state = match cloned_diglett.allocation_state(address).await {
-            									Ok(alloc_state) => alloc_state,
-            									Err(status) => panic!(
-                                                    "Panicked while retrieving allocation state for address {}! (status: {})",
-                                                    address,
-            										status
-                                                ),
-            								};
-                            let top_rir = match cloned_diglett.rir(address, true).await {
-            									Ok(rir) => rir,
-            									Err(status) => panic!(
-                                                    "Panicked while retrieving top RIR for address {}! (status: {})",
-                                                    address,
-            										status
-                                                ),
-            								};
-                            let rir = match cloned_diglett.rir(address, false).await {
-                                Ok(rir) => rir,
-                                Err(status) => panic!(
-                                    "Panicked while retrieving rir for address {}! (status: {})",
-                                    address, status
-                                ),
+                            let AddressResponse {
+                                allocation_state: alloc_state,
+                                top_rir,
+                                rir,
+                                asn: autsys,
+                                asn_name: autsys_name,
+                                country,
+                                rpki,
+                                geo,
+                                special_purpose,
+                                ..
+                            } = match cloned_diglett.address(address).await {
+                                Ok(response) => response,
+                                Err(error) => {
+                                    cloned_response_tx
+                                        .send(PidgeyCommandResponse {
+                                            id: command.id,
+                                            payload: failed(address, error),
+                                        })
+                                        .await
+                                        .unwrap();
+                                    return;
+                                }
                             };
-                            let autsys = match cloned_diglett.asn(address).await {
-            									Ok(asn) => asn,
-            									Err(status) => panic!(
-                                                    "Panicked while retrieving AS number for address {}! (status: {})",
-                                                    address,
-            										status
-                                                ),
-            								};
-                            let country = match cloned_diglett.country(address).await {
-            									Ok(country) => country,
-            									Err(status) => panic!(
-                                                    "Panicked while retrieving country for address {}! (status: {})",
-                                                    address,
-            										status
-                                                ),
-            								};
 
                             if alloc_state == AllocationState::Reserved
                                 || alloc_state == AllocationState::Unallocated
+                                || alloc_state == AllocationState::Multicast
+                                || alloc_state == AllocationState::Bogon
                             {
                                 cloned_response_tx
                                     .send(PidgeyCommandResponse {
@@ -237,6 +235,10 @@ pub async fn run(
                                                 autsys,
                                                 country,
                                                 online: false,
+                                                rpki,
+                                                geo: geo.map(Box::new),
+                                                special_purpose: special_purpose.map(Box::new),
+                                                autsys_name,
                                             },
                                     })
                                     .await
@@ -262,6 +264,10 @@ pub async fn run(
                                                 autsys,
                                                 country,
                                                 online,
+                                                rpki,
+                                                geo: geo.map(Box::new),
+                                                special_purpose: special_purpose.map(Box::new),
+                                                autsys_name,
                                             },
                                     })
                                     .await
@@ -273,9 +279,12 @@ pub async fn run(
                             cloned_response_tx
                                 .send(PidgeyCommandResponse {
                                     id: command.id,
-                                    payload: mtilib::pidgey::PidgeyCommandResponsePayload::AllocationState {
-                                        value: cloned_diglett.allocation_state(address).await.unwrap(),
-                                    }
+                                    payload: match cloned_diglett.allocation_state(address).await {
+                                        Ok(value) => {
+                                            PidgeyCommandResponsePayload::AllocationState { value }
+                                        }
+                                        Err(error) => failed(address, error),
+                                    },
                                 })
                                 .await
                                 .unwrap()
@@ -285,8 +294,9 @@ pub async fn run(
                             cloned_response_tx
                                 .send(PidgeyCommandResponse {
                                     id: command.id,
-                                    payload: mtilib::pidgey::PidgeyCommandResponsePayload::Rir {
-                                        value: cloned_diglett.rir(address, top).await.unwrap(),
+                                    payload: match cloned_diglett.rir(address, top).await {
+                                        Ok(value) => PidgeyCommandResponsePayload::Rir { value },
+                                        Err(error) => failed(address, error),
                                     },
                                 })
                                 .await
@@ -297,8 +307,9 @@ pub async fn run(
                             cloned_response_tx
                                 .send(PidgeyCommandResponse {
                                     id: command.id,
-                                    payload: mtilib::pidgey::PidgeyCommandResponsePayload::Autsys {
-                                        value: cloned_diglett.asn(address).await.unwrap(),
+                                    payload: match cloned_diglett.asn(address).await {
+                                        Ok(value) => PidgeyCommandResponsePayload::Autsys { value },
+                                        Err(error) => failed(address, error),
                                     },
                                 })
                                 .await
@@ -309,10 +320,12 @@ pub async fn run(
                             cloned_response_tx
                                 .send(PidgeyCommandResponse {
                                     id: command.id,
-                                    payload:
-                                        mtilib::pidgey::PidgeyCommandResponsePayload::Country {
-                                            value: cloned_diglett.country(address).await.unwrap(),
-                                        },
+                                    payload: match cloned_diglett.country(address).await {
+                                        Ok(value) => {
+                                            PidgeyCommandResponsePayload::Country { value }
+                                        }
+                                        Err(error) => failed(address, error),
+                                    },
                                 })
                                 .await
                                 .unwrap()
@@ -348,6 +361,32 @@ pub async fn run(
 
                             cloned_response_tx.send(cmd).await.unwrap()
                         }
+                        PidgeyCommandPayload::Lookup { addresses } => {
+                            let _permit = cloned_worker_permits.acquire().await.unwrap();
+                            let addresses =
+                                addresses.into_iter().map(IpAddr::V4).collect::<Vec<_>>();
+                            let payload = match cloned_diglett.lookup_addresses(&addresses).await {
+                                Ok(values) => PidgeyCommandResponsePayload::Lookup { values },
+                                Err(error) => {
+                                    error!(
+                                        "Failed to look up {} addresses in Diglett! ({})",
+                                        addresses.len(),
+                                        error
+                                    );
+                                    PidgeyCommandResponsePayload::Failed {
+                                        reason: error.to_string(),
+                                    }
+                                }
+                            };
+
+                            cloned_response_tx
+                                .send(PidgeyCommandResponse {
+                                    id: command.id,
+                                    payload,
+                                })
+                                .await
+                                .unwrap()
+                        }
                         _ => {}
                     },
                     Err(error) => error!("{}", error),
@@ -4,7 +4,8 @@ use serde::Deserialize;
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub api: SettingsAPI,
-    pub diglett: Option<SettingsDiglett>,
+    #[serde(default)]
+    pub diglett: SettingsDiglett,
     #[serde(default = "_default_max_workers")]
     pub max_workers: usize,
     pub pidgeotto: Option<SettingsPidgeotto>,
@@ -19,6 +20,22 @@ const fn _default_max_workers() -> usize {
 #[derive(Debug, Deserialize)]
 pub struct SettingsDiglett {
     pub address: Option<String>,
+    /// Seconds between the re-discoveries of the Diglett units
+    #[serde(default = "_default_diglett_rediscover")]
+    pub rediscover: u64,
+}
+
+impl Default for SettingsDiglett {
+    fn default() -> Self {
+        SettingsDiglett {
+            address: None,
+            rediscover: _default_diglett_rediscover(),
+        }
+    }
+}
+
+const fn _default_diglett_rediscover() -> u64 {
+    300
 }
 
 #[derive(Debug, Deserialize)]